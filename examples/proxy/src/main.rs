@@ -1,15 +1,13 @@
-use bytes::BytesMut;
 use smoo::guid::Guid;
 use smoo::net::connection::Connection;
 use smoo::net::udp_conn::UdpConnection;
-use smoo::net::{encoding::Encodable, Packet, PacketData};
+use smoo::net::{Packet, PacketData};
 use smoo::types::Result;
 use std::ops::Not;
 use std::time::Instant;
-use std::{io::Cursor, net::SocketAddr, net::ToSocketAddrs};
-use tokio;
+use std::{net::SocketAddr, net::ToSocketAddrs};
 use tokio::net::UdpSocket;
-use tokio::net::{TcpListener, TcpSocket, TcpStream};
+use tokio::net::{TcpListener, TcpStream};
 use tracing::Instrument;
 use tracing_subscriber::EnvFilter;
 
@@ -93,7 +91,6 @@ async fn main() -> Result<()> {
         tracing::info!("new client connection: {}", addr);
         let span = tracing::info_span!("cli", addr = addr.ip().to_string());
 
-        let remote_addrs = remote_addrs.clone();
         tokio::spawn(
             async move {
                 let result = proxy_client(from_socket, local_bind.1, remote_addrs).await;
@@ -104,8 +101,6 @@ async fn main() -> Result<()> {
             .instrument(span),
         );
     }
-
-    Ok(())
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -142,10 +137,10 @@ async fn proxy_client(
     let loc_udp_port = loc_udp_addr.port();
     tracing::debug!("Binding udp to: {}", loc_udp_addr);
 
-    let mut cli = Connection::new(cli_sock);
-    let mut serv = Connection::new(serv_sock);
+    let mut cli = Connection::new(cli_sock)?;
+    let mut serv = Connection::new(serv_sock)?;
     let mut udp = UdpConnection::from_connection(udp, serv_udp_addr);
-    let mut use_udp = true;
+    let use_udp = true;
     let mut last_tag_packet = Instant::now();
 
     tracing::info!("Client setup and ready");
@@ -186,7 +181,7 @@ async fn proxy_client(
             _ => {}
         }
 
-        let (origin_conn, dest_conn) = match origin {
+        let (_origin_conn, dest_conn) = match origin {
             Origin::Client => (&mut cli, &mut serv),
             Origin::Server => (&mut serv, &mut cli),
         };
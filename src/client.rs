@@ -1,33 +1,94 @@
+use crate::cmds::ClientCommand;
 use crate::cmds::Command;
 use crate::cmds::ServerCommand;
 use crate::guid::Guid;
-use crate::net::connection;
 use crate::net::connection::Connection;
-use crate::net::udp_conn::UdpConnection;
+use crate::net::protocol::{Extensions, ProtocolInfo};
+use crate::net::udp_conn::{UdpConnection, HOLE_PUNCH_INTERVAL};
+use crate::net::udp_mux::UdpMux;
 use crate::net::Packet;
 use crate::net::PacketData;
+use crate::net::PacketType;
+use crate::net::RawPacket;
 use crate::settings::SyncSettings;
+use crate::shine::ShineSyncState;
+use crate::status::ConnectionMode;
 use crate::types::ClientInitError;
 use crate::types::{Costume, SMOError};
 use crate::types::{EncodingError, Result};
 use std::collections::{HashMap, HashSet};
 use std::net::IpAddr;
-use std::net::Ipv4Addr;
-use std::net::SocketAddr;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::io::AsyncWriteExt;
 use tokio::net::TcpStream;
-use tokio::net::UdpSocket;
 use tokio::select;
 use tokio::sync::{mpsc, RwLock};
-use tracing::info;
-use tracing::info_span;
-use tracing::instrument;
+use tokio::time::{self, Interval, MissedTickBehavior};
 
 pub type ClientMap = HashMap<Guid, SyncClient>;
 pub type SyncClient = Arc<RwLock<ClientData>>;
 
+/// How long the data of a player who left is kept for them to rejoin
+pub const DISCONNECTED_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+/// Most players whose data is kept after they leave
+pub const MAX_DISCONNECTED: usize = 256;
+
+/// Client data of players who left, along with when they left.
+///
+/// Entries expire after [`DISCONNECTED_TIMEOUT`], and once
+/// [`MAX_DISCONNECTED`] players are kept the one gone longest is dropped.
+#[derive(Debug, Default)]
+pub struct DisconnectedClients {
+    clients: HashMap<Guid, (SyncClient, Instant)>,
+}
+
+impl DisconnectedClients {
+    pub fn insert(&mut self, guid: Guid, data: SyncClient, now: Instant) {
+        self.evict(now);
+        if self.clients.len() >= MAX_DISCONNECTED && !self.clients.contains_key(&guid) {
+            let oldest = self
+                .clients
+                .iter()
+                .min_by_key(|(_, (_, left_at))| *left_at)
+                .map(|(guid, _)| *guid);
+            if let Some(oldest) = oldest {
+                self.clients.remove(&oldest);
+            }
+        }
+        self.clients.insert(guid, (data, now));
+    }
+
+    pub fn get(&self, guid: &Guid) -> Option<&SyncClient> {
+        self.clients.get(guid).map(|(data, _)| data)
+    }
+
+    pub fn remove(&mut self, guid: &Guid) -> Option<SyncClient> {
+        self.clients.remove(guid).map(|(data, _)| data)
+    }
+
+    /// Takes a player's data back out, unless it has expired
+    pub fn restore(&mut self, guid: &Guid, now: Instant) -> Option<SyncClient> {
+        let (data, left_at) = self.clients.remove(guid)?;
+        (now.saturating_duration_since(left_at) < DISCONNECTED_TIMEOUT).then_some(data)
+    }
+
+    /// Drops the data of players who left too long ago
+    pub fn evict(&mut self, now: Instant) {
+        self.clients.retain(|_, (_, left_at)| {
+            now.saturating_duration_since(*left_at) < DISCONNECTED_TIMEOUT
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.clients.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+}
+
 #[derive(Debug)]
 pub struct Client {
     pub display_name: String,
@@ -36,6 +97,11 @@ pub struct Client {
     pub alive: bool,
     pub conn: Connection,
     pub udp_conn: UdpConnection,
+    /// Where `udp_conn` is registered, to drop its route once the client leaves
+    pub udp_mux: UdpMux,
+    pub extensions: Extensions,
+    /// Resends hole punches until udp is up and keeps the NAT mapping open after
+    pub hole_punch: Interval,
     pub to_coord: mpsc::Sender<Command>,
     pub from_server: mpsc::Receiver<Command>,
 }
@@ -43,16 +109,22 @@ pub struct Client {
 #[derive(Default, Clone, Debug)]
 pub struct ClientData {
     pub name: String,
+    pub ip: Option<IpAddr>,
+    pub connected_at: Option<Instant>,
+    pub mode: ConnectionMode,
     pub shine_sync: HashSet<i32>,
     pub scenario: u8,
     pub is_2d: bool,
     pub is_seeking: bool,
     pub last_game_packet: Option<Packet>,
-    pub speedrun_start: bool,
+    pub shine_sync_state: ShineSyncState,
     pub loaded_save: bool,
     pub time: Duration,
     pub settings: SyncSettings,
     pub costume: Costume,
+    pub capture: Option<String>,
+    /// Protocol extensions agreed on when connecting
+    pub extensions: Extensions,
 }
 
 #[derive(Debug)]
@@ -63,8 +135,9 @@ enum Origin {
 
 #[derive(Debug)]
 enum ClientEvent {
-    Packet(Packet),
+    Frame(RawPacket),
     Command(Command),
+    HolePunch,
 }
 
 impl Client {
@@ -74,8 +147,11 @@ impl Client {
 
             tracing::trace!("Event: {:?}", &event);
             let result = match event {
-                Ok((Origin::External, ClientEvent::Packet(p))) => self.handle_packet(p).await,
-                Ok((Origin::Internal, ClientEvent::Packet(p))) => self.send_packet(&p).await,
+                Ok((_, ClientEvent::Frame(f))) => self.handle_frame(f).await,
+                Ok((_, ClientEvent::HolePunch)) => {
+                    self.udp_conn.check_liveness(Instant::now());
+                    self.udp_conn.send_hole_punch().await
+                }
                 Ok((_, ClientEvent::Command(c))) => self.handle_command(c).await,
                 Err(SMOError::Encoding(EncodingError::ConnectionClose))
                 | Err(SMOError::Encoding(EncodingError::ConnectionReset))
@@ -83,12 +159,22 @@ impl Client {
                     self.alive = false;
                     break;
                 }
+                Err(SMOError::Encoding(e)) => {
+                    self.reject_malformed(e);
+                    break;
+                }
                 Err(e) => Err(e),
             };
 
             if let Err(e) = result {
                 tracing::warn!("Error with client {}: {}", self.guid, e)
             }
+
+            if self.udp_conn.take_mode_change() {
+                let mode = self.udp_conn.mode();
+                tracing::info!("Client {} now using {}", self.display_name, mode);
+                self.data.write().await.mode = mode;
+            }
         }
 
         self.disconnect().await?;
@@ -97,13 +183,14 @@ impl Client {
 
     async fn read_event(&mut self) -> Result<(Origin, ClientEvent)> {
         let event = select! {
-            packet = self.conn.read_packet() => {
-                (Origin::External, ClientEvent::Packet(packet?))
+            frame = self.conn.read_frame() => {
+                (Origin::External, ClientEvent::Frame(frame?))
             },
-            udp_packet = self.udp_conn.read_packet() => {
+            udp_frame = self.udp_conn.read_frame() => {
                 tracing::trace!("Got udp event!");
-                (Origin::External, ClientEvent::Packet(udp_packet?))
+                (Origin::External, ClientEvent::Frame(udp_frame?))
             },
+            _ = self.hole_punch.tick() => (Origin::Internal, ClientEvent::HolePunch),
             command = self.from_server.recv() => (Origin::Internal, ClientEvent::Command(command.ok_or(SMOError::RecvChannel)?)),
         };
         Ok(event)
@@ -111,6 +198,7 @@ impl Client {
 
     pub async fn disconnect(mut self) -> Result<()> {
         tracing::warn!("Client {} disconnected", self.display_name);
+        self.udp_mux.unregister(self.guid, self.udp_conn).await;
         self.to_coord
             .send(Command::Server(ServerCommand::DisconnectPlayer {
                 guid: self.guid,
@@ -120,6 +208,39 @@ impl Client {
         Ok(())
     }
 
+    /// Decodes a received packet if the server has to look at it, otherwise
+    /// hands the frame to the coordinator as is
+    async fn handle_frame(&mut self, frame: RawPacket) -> Result<()> {
+        let decoded = if frame.needs_decode() {
+            frame.decode().map(Some)
+        } else {
+            frame.check_size().map(|_| None)
+        };
+
+        match decoded {
+            Ok(Some(packet)) => self.handle_packet(packet).await,
+            Ok(None) => {
+                self.to_coord.send(Command::Relay(frame)).await?;
+                Ok(())
+            }
+            Err(e) => {
+                self.reject_malformed(e);
+                Ok(())
+            }
+        }
+    }
+
+    /// A client sending garbage can't be trusted to stay in sync with the
+    /// stream, so it gets disconnected
+    fn reject_malformed(&mut self, e: EncodingError) {
+        tracing::warn!(
+            "Disconnecting client {} after a malformed packet: {}",
+            self.display_name,
+            e
+        );
+        self.alive = false;
+    }
+
     async fn handle_packet(&mut self, packet: Packet) -> Result<()> {
         tracing::debug!("Handling packet: {}", &packet.data.get_type_name());
         let send_to_coord = match &packet.data {
@@ -134,34 +255,12 @@ impl Client {
             PacketData::Game {
                 is_2d,
                 scenario_num,
-                stage,
+                ..
             } => {
                 let mut data = self.data.write().await;
                 data.is_2d = *is_2d;
                 data.scenario = *scenario_num;
                 data.last_game_packet = Some(packet.clone());
-                if stage == "CapWorldHomeStage" && *scenario_num == 0 {
-                    data.speedrun_start = true;
-                    data.shine_sync.clear();
-                }
-
-                true
-            }
-            PacketData::Tag {
-                update_type,
-                is_it,
-                seconds,
-                minutes,
-            } => {
-                let mut data = self.data.write().await;
-                match update_type {
-                    crate::net::TagUpdate::Time => {
-                        data.time = Duration::from_secs(*seconds as u64 + *minutes as u64 * 60);
-                    }
-                    crate::net::TagUpdate::State => {
-                        data.is_seeking = *is_it;
-                    }
-                }
                 true
             }
             PacketData::Shine { shine_id, .. } => {
@@ -171,10 +270,27 @@ impl Client {
                 }
                 true
             }
+            PacketData::Capture { model } => {
+                let mut data = self.data.write().await;
+                data.capture = (!model.is_empty()).then(|| model.clone());
+                true
+            }
             PacketData::UdpInit { port } => {
-                self.udp_conn.set_client_port(*port);
+                self.udp_conn.begin_hole_punch(*port);
+                self.udp_conn.send_hole_punch().await?;
+                self.hole_punch.reset();
                 false
             }
+            PacketData::HolePunch => false,
+            PacketData::Ping { nonce } => {
+                if self.extensions.contains(Extensions::PING) {
+                    let pong = Packet::new(Guid::default(), PacketData::Ping { nonce: *nonce });
+                    self.conn.write_packet(&pong).await?;
+                }
+                false
+            }
+            // Only the server sends messages
+            PacketData::ServerMessage { .. } => false,
             _ => true,
         };
 
@@ -189,14 +305,6 @@ impl Client {
         self.conn.read_packet().await
     }
 
-    fn parse_packet(&mut self) -> Result<Packet> {
-        match self.conn.parse_packet() {
-            Err(e) => Err(e),
-            Ok(Some(t)) => Ok(t),
-            Ok(None) => Err(EncodingError::NotEnoughData.into()),
-        }
-    }
-
     async fn handle_command(&mut self, command: Command) -> Result<()> {
         match command {
             Command::Packet(p) => {
@@ -208,7 +316,17 @@ impl Client {
                     self.send_packet(&p).await?;
                 }
             }
-            _ => todo!(),
+            Command::Relay(frame) => {
+                if frame.header.id != self.guid {
+                    self.send_frame(&frame).await?;
+                }
+            }
+            Command::Client(ClientCommand::SelfAddressed(p)) => {
+                self.conn.write_packet(&p).await?;
+            }
+            Command::Cli(..) | Command::Server(_) => {
+                tracing::warn!("Client {} received a coordinator only command", self.guid);
+            }
         }
         Ok(())
     }
@@ -223,16 +341,20 @@ impl Client {
             );
             tracing::debug!("Udp conn: {:?}", self.udp_conn);
 
-            if self.udp_conn.is_client_udp() {
-                // Use UDP traffic
-                match packet.data {
-                    PacketData::Player { .. } | PacketData::Cap { .. } => {
-                        self.udp_conn.write_packet(packet).await
+            let is_movement = matches!(
+                packet.data,
+                PacketData::Player { .. } | PacketData::Cap { .. }
+            );
+            if is_movement && self.udp_conn.is_client_udp() {
+                // Use UDP traffic, falling back to TCP if the send fails
+                match self.udp_conn.write_packet(packet).await {
+                    Err(e @ SMOError::Io(_)) => {
+                        tracing::debug!("Udp send to {} failed: {}", self.display_name, e);
+                        self.conn.write_packet(packet).await
                     }
-                    _ => self.conn.write_packet(packet).await,
+                    result => result,
                 }
             } else {
-                // Fall back to TCP traffic
                 self.conn.write_packet(packet).await
             }
         } else {
@@ -240,56 +362,103 @@ impl Client {
         }
     }
 
+    async fn send_frame(&mut self, frame: &RawPacket) -> Result<()> {
+        let is_movement = matches!(
+            frame.header.packet_type(),
+            Some(PacketType::Player | PacketType::Cap)
+        );
+        if is_movement && self.udp_conn.is_client_udp() {
+            match self.udp_conn.write_frame(frame).await {
+                Err(e @ SMOError::Io(_)) => {
+                    tracing::debug!("Udp send to {} failed: {}", self.display_name, e);
+                    self.conn.write_frame(frame).await
+                }
+                result => result,
+            }
+        } else {
+            self.conn.write_frame(frame).await
+        }
+    }
+
     pub async fn initialize_client(
         socket: TcpStream,
         to_coord: mpsc::Sender<Command>,
-        udp_port: u16,
+        udp: UdpMux,
         settings: SyncSettings,
     ) -> Result<()> {
         let (to_cli, from_server) = mpsc::channel(10);
-        let tcp_sock_addr = socket.peer_addr().expect("Couldn't get tcp peer address");
 
         let l_set = settings.read().await;
         let max_players = l_set.server.max_players;
         drop(l_set);
 
         tracing::debug!("Initializing connection");
-        let mut conn = Connection::new(socket);
+        let mut conn = Connection::new(socket)?;
+        let tcp_sock_addr = conn.addr;
         conn.write_packet(&Packet::new(
             Guid::default(),
-            PacketData::Init { max_players },
+            PacketData::Init {
+                max_players,
+                protocol: None,
+            },
         ))
         .await?;
 
-        let local_udp_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), udp_port);
-        let udp = UdpSocket::bind(local_udp_addr).await?;
-        let local_udp_addr = udp.local_addr().expect("Failed to unwrap udp port");
-        tracing::debug!("Binding udp to: {:?}", local_udp_addr);
-
-        tracing::debug!("setting new udp connection");
-        let mut udp_conn = UdpConnection::new(udp, tcp_sock_addr.ip());
+        let mut hole_punch = time::interval(HOLE_PUNCH_INTERVAL);
+        hole_punch.set_missed_tick_behavior(MissedTickBehavior::Delay);
 
         tracing::debug!("Waiting for reply");
-        let connect = conn.read_packet().await?;
+        let mut connect = conn.read_packet().await?;
 
         let new_player = match connect.data {
             PacketData::Connect {
                 client_name: ref name,
+                ref mut protocol,
                 ..
             } => {
+                // Kept out of the connect packet relayed to other players
+                let extensions = match protocol.take() {
+                    Some(client_protocol) => {
+                        let agreed = ProtocolInfo::server().negotiate(&client_protocol);
+                        tracing::debug!("Agreed on protocol {:?}", agreed);
+                        conn.write_packet(&Packet::new(
+                            Guid::default(),
+                            PacketData::Init {
+                                max_players,
+                                protocol: Some(agreed),
+                            },
+                        ))
+                        .await?;
+                        agreed.extensions
+                    }
+                    None => Extensions::NONE,
+                };
+
+                tracing::debug!("setting new udp connection");
+                let local_udp_addr = udp.local_addr()?;
+                let udp_conn = udp.connection(connect.id, tcp_sock_addr.ip()).await;
+
                 let data = ClientData {
                     settings,
                     name: name.clone(),
+                    ip: Some(tcp_sock_addr.ip()),
+                    connected_at: Some(Instant::now()),
+                    extensions,
                     ..ClientData::default()
                 };
 
-                conn.write_packet(&Packet::new(
-                    Guid::default(),
-                    PacketData::UdpInit {
-                        port: local_udp_addr.port(),
-                    },
-                ))
-                .await?;
+                let udp_init = conn
+                    .write_packet(&Packet::new(
+                        Guid::default(),
+                        PacketData::UdpInit {
+                            port: local_udp_addr.port(),
+                        },
+                    ))
+                    .await;
+                if let Err(e) = udp_init {
+                    udp.unregister(connect.id, udp_conn).await;
+                    return Err(e);
+                }
 
                 let data = Arc::new(RwLock::new(data));
 
@@ -304,10 +473,13 @@ impl Client {
                     from_server,
                     conn,
                     udp_conn,
+                    udp_mux: udp.clone(),
+                    extensions,
+                    hole_punch,
                 };
 
                 Ok(Command::Server(ServerCommand::NewPlayer {
-                    cli: client,
+                    cli: Box::new(client),
                     connect_packet: Box::new(connect),
                     comm: to_cli,
                 }))
@@ -316,7 +488,57 @@ impl Client {
         }?;
         tracing::debug!("Initialized player");
 
-        to_coord.send(new_player).await?;
+        let sent = to_coord.send(new_player).await;
+        // Without a coordinator to hand it to, the client cleans up after itself
+        if let Err(mpsc::error::SendError(Command::Server(ServerCommand::NewPlayer {
+            cli, ..
+        }))) = sent
+        {
+            cli.disconnect().await?;
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn guid(n: u8) -> Guid {
+        [n; 16].into()
+    }
+
+    #[test]
+    fn disconnected_clients_expire() {
+        let now = Instant::now();
+        let mut disconnected = DisconnectedClients::default();
+        disconnected.insert(guid(1), SyncClient::default(), now);
+        disconnected.insert(
+            guid(2),
+            SyncClient::default(),
+            now + DISCONNECTED_TIMEOUT / 2,
+        );
+
+        let later = now + DISCONNECTED_TIMEOUT;
+        assert!(disconnected.restore(&guid(1), later).is_none());
+        disconnected.evict(later);
+        assert_eq!(disconnected.len(), 1);
+        assert!(disconnected.restore(&guid(2), later).is_some());
+        assert!(disconnected.is_empty());
+    }
+
+    #[test]
+    fn disconnected_clients_are_capped() {
+        let now = Instant::now();
+        let mut disconnected = DisconnectedClients::default();
+        for n in 0..=MAX_DISCONNECTED {
+            let left_at = now + Duration::from_secs(n as u64);
+            let guid: Guid = (n as u128).to_le_bytes().into();
+            disconnected.insert(guid, SyncClient::default(), left_at);
+        }
+
+        assert_eq!(disconnected.len(), MAX_DISCONNECTED);
+        assert!(disconnected.get(&0u128.to_le_bytes().into()).is_none());
+        assert!(disconnected.get(&1u128.to_le_bytes().into()).is_some());
+    }
+}
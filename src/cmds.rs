@@ -1,24 +1,31 @@
 use crate::{
     client::Client,
     guid::Guid,
-    net::{ConnectionType, Packet},
+    net::{Packet, RawPacket},
+    settings::FlipPovSettings,
+    types::Result,
 };
 use std::{convert::Infallible, str::FromStr};
 
 use clap::{Parser, Subcommand, ValueEnum};
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot};
+
+pub type CliReply = oneshot::Sender<Result<String>>;
 
 #[derive(Debug)]
 pub enum Command {
     Packet(Packet),
-    Cli(CliCommand),
+    /// A packet relayed as received, without being decoded
+    Relay(RawPacket),
+    Cli(CliCommand, CliReply),
     Server(ServerCommand),
+    Client(ClientCommand),
 }
 
 #[derive(Debug)]
 pub enum ServerCommand {
     NewPlayer {
-        cli: Client,
+        cli: Box<Client>,
         connect_packet: Box<Packet>,
         comm: mpsc::Sender<Command>,
     },
@@ -28,7 +35,14 @@ pub enum ServerCommand {
     Shutdown,
 }
 
+#[derive(Debug)]
+pub enum ClientCommand {
+    /// Deliver a packet to the client even if it carries the client's own id
+    SelfAddressed(Packet),
+}
+
 #[derive(Parser, Debug)]
+#[clap(no_binary_name = true)]
 pub struct Cli {
     #[clap(subcommand)]
     pub cmd: CliCommand,
@@ -42,6 +56,7 @@ pub enum CliCommand {
     Send {
         stage: String,
         id: String,
+        #[clap(allow_hyphen_values = true)]
         scenario: i8,
         players: Vec<PlayerSelect>,
     },
@@ -54,14 +69,24 @@ pub enum CliCommand {
     Rejoin {
         players: Vec<PlayerSelect>,
     },
+    /// Show a message to every player whose client supports server messages
+    Say {
+        #[clap(required = true)]
+        message: Vec<String>,
+    },
     #[clap(subcommand)]
     Scenario(ScenarioCommand),
     #[clap(subcommand)]
     Tag(TagCommand),
+    #[clap(alias = "maxplayers")]
     MaxPlayers {
         player_count: u16,
     },
-    List,
+    List {
+        /// Print the player list as json
+        #[clap(long)]
+        json: bool,
+    },
     #[clap(subcommand)]
     Flip(FlipCommand),
     #[clap(subcommand)]
@@ -87,17 +112,28 @@ pub enum TagCommand {
     },
     Start {
         countdown: u8,
-        seekers: String,
+        #[clap(required = true)]
+        seekers: Vec<PlayerSelect>,
     },
+    Stop,
 }
 
 #[derive(Subcommand, Debug, Clone)]
 pub enum FlipCommand {
     List,
-    Add { player: Guid },
-    Remove { player: Guid },
-    Set { is_flipped: bool },
-    Pov { value: FlipValues },
+    Add {
+        player: Guid,
+    },
+    Remove {
+        player: Guid,
+    },
+    Set {
+        is_flipped: bool,
+    },
+    Pov {
+        #[clap(value_enum)]
+        value: FlipValues,
+    },
 }
 
 #[derive(Subcommand, Debug, Clone)]
@@ -105,7 +141,18 @@ pub enum ShineCommand {
     List,
     Clear,
     Sync,
-    Send { id: u32, player: PlayerSelect },
+    Send {
+        id: u32,
+        players: Vec<PlayerSelect>,
+    },
+    /// Stop sending and sharing moons for these players
+    Exclude {
+        players: Vec<PlayerSelect>,
+    },
+    /// Undo `exclude`
+    Include {
+        players: Vec<PlayerSelect>,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -117,7 +164,7 @@ pub enum PlayerSelect {
 impl FromStr for PlayerSelect {
     type Err = Infallible;
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
         Ok(if s == "*" {
             Self::AllPlayers
         } else {
@@ -133,10 +180,41 @@ pub enum FlipValues {
     Others,
 }
 
-impl FromStr for FlipValues {
-    type Err = Infallible;
+impl From<FlipValues> for FlipPovSettings {
+    fn from(value: FlipValues) -> Self {
+        match value {
+            FlipValues::Both => Self::Both,
+            FlipValues::Player => Self::Player,
+            FlipValues::Others => Self::Others,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn send_accepts_scenario_minus_one() {
+        let cli = Cli::try_parse_from(["send", "sand", "x", "-1", "Mario"]).unwrap();
+        match cli.cmd {
+            CliCommand::Send {
+                stage,
+                scenario,
+                players,
+                ..
+            } => {
+                assert_eq!(stage, "sand");
+                assert_eq!(scenario, -1);
+                assert_eq!(players.len(), 1);
+            }
+            cmd => panic!("Parsed {:?} instead of send", cmd),
+        }
+    }
 
-    fn from_str(_s: &str) -> Result<Self, Self::Err> {
-        unimplemented!()
+    #[test]
+    fn tag_start_needs_seekers() {
+        assert!(Cli::try_parse_from(["tag", "start", "5"]).is_err());
+        assert!(Cli::try_parse_from(["tag", "start", "5", "Mario"]).is_ok());
     }
 }
@@ -1,34 +1,101 @@
 use crate::{
-    client::{ClientMap, SyncClient},
-    cmds::{Command, ServerCommand},
+    client::{ClientMap, DisconnectedClients, SyncClient},
+    cmds::{
+        CliCommand, ClientCommand, Command, FlipCommand, PlayerSelect, ScenarioCommand,
+        ServerCommand, ShineCommand, TagCommand,
+    },
     guid::Guid,
-    net::{connection, ConnectionType, Packet, PacketData},
-    settings::SyncSettings,
-    types::{ClientInitError, Result, SMOError},
+    net::{
+        protocol::Extensions, udp_conn::UdpStats, ConnectionType, Packet, PacketData, RawPacket,
+        TagUpdate, SERVER_MESSAGE_SIZE,
+    },
+    settings::{
+        read_settings, save_settings, FlipPovSettings, FlipSettings, ShineSyncSettings,
+        SyncSettings,
+    },
+    shine::{save_shines, ShineRecord, ShineSyncState, SyncShineBag, RESUME_SYNC_DELAY},
+    stages::input_to_stage,
+    status::{format_duration, format_table, ConnectionMode, PlayerStatus, TagStatus},
+    tag::{TagGame, TAG_UPDATE_INTERVAL},
+    types::{ClientInitError, EncodingError, Quaternion, Result, SMOError},
 };
 
 use std::{
     collections::{HashMap, HashSet},
-    net::IpAddr,
+    fmt::Display,
     sync::Arc,
-    time::Duration,
+    time::{Duration, Instant},
+};
+use tokio::{
+    select,
+    sync::{broadcast, mpsc},
+    time::MissedTickBehavior,
 };
-use tokio::sync::{mpsc, RwLock};
 use tracing::{info_span, Instrument};
-type SyncShineBag = Arc<RwLock<HashSet<i32>>>;
+
+/// Scenario the game treats as "no scenario" when merging scenarios
+const UNKNOWN_SCENARIO: u8 = 200;
 
 pub struct Coordinator {
     pub shine_bag: SyncShineBag,
     pub settings: SyncSettings,
     pub clients: ClientMap,
+    /// Data of players who left, kept so it can be restored when they reconnect
+    pub disconnected_clients: DisconnectedClients,
     pub to_clients: HashMap<Guid, mpsc::Sender<Command>>,
     pub from_clients: mpsc::Receiver<Command>,
+    pub tag_game: TagGame,
+    pub events: broadcast::Sender<ServerEvent>,
+    /// Datagrams the udp socket threw away, shown by `list`
+    pub udp_stats: Arc<UdpStats>,
+}
+
+/// Notable server happenings, published for integrations such as the Discord bot
+#[derive(Debug, Clone)]
+pub enum ServerEvent {
+    PlayerJoined { name: String },
+    PlayerLeft { name: String },
+    ShineCollected { name: String, shine_id: i32 },
+    PlayerBanned { name: String },
+    TagGameWon { name: String, time: Duration },
+    TagGameOver,
+}
+
+impl Display for ServerEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::PlayerJoined { name } => write!(f, "{} joined the server", name),
+            Self::PlayerLeft { name } => write!(f, "{} left the server", name),
+            Self::ShineCollected { name, shine_id } => {
+                write!(f, "{} collected moon {}", name, shine_id)
+            }
+            Self::PlayerBanned { name } => write!(f, "{} was banned", name),
+            Self::TagGameWon { name, time } => write!(
+                f,
+                "Tag game over, {} wins after hiding for {}",
+                name,
+                format_tag_time(*time)
+            ),
+            Self::TagGameOver => write!(f, "Tag game over"),
+        }
+    }
 }
 
 impl Coordinator {
     pub async fn handle_commands(mut self) {
+        let mut tag_updates = tokio::time::interval(TAG_UPDATE_INTERVAL);
+        // Ticks missed while no game runs must not fire as a burst on the next start
+        tag_updates.set_missed_tick_behavior(MissedTickBehavior::Delay);
         loop {
-            let cmd = self.from_clients.recv().await;
+            let cmd = select! {
+                cmd = self.from_clients.recv() => cmd,
+                _ = tag_updates.tick(), if self.tag_game.is_active() => {
+                    if let Err(e) = self.update_tag_game().await {
+                        tracing::warn!("Tag game update failed: {e}")
+                    }
+                    continue;
+                }
+            };
             if let Some(c) = cmd {
                 let result = self.handle_command(c).await;
                 match result {
@@ -51,15 +118,45 @@ impl Coordinator {
                 ServerCommand::DisconnectPlayer { guid } => self.disconnect_player(guid).await?,
                 ServerCommand::Shutdown => return Ok(false),
             },
+            Command::Relay(frame) => self.relay(frame).await?,
             Command::Packet(packet) => {
                 match &packet.data {
                     PacketData::Costume(_) => {
                         self.sync_all_shines().await?;
                     }
-                    PacketData::Shine { shine_id, .. } => {
-                        self.shine_bag.write().await.insert(*shine_id);
+                    PacketData::Shine { shine_id, is_grand } => {
+                        let stage =
+                            match &self.get_client(&packet.id)?.read().await.last_game_packet {
+                                Some(Packet {
+                                    data: PacketData::Game { stage, .. },
+                                    ..
+                                }) => Some(stage.clone()),
+                                _ => None,
+                            };
+                        let is_new = {
+                            let mut shine_bag = self.shine_bag.write().await;
+                            let is_new = !shine_bag.contains_key(shine_id);
+                            if is_new {
+                                let record =
+                                    ShineRecord::new(*shine_id, *is_grand, packet.id, stage);
+                                shine_bag.insert(*shine_id, record);
+                            }
+                            is_new
+                        };
                         tracing::info!("Got moon {shine_id}");
-                        self.sync_all_shines().await?;
+                        if is_new {
+                            self.persist_shines().await;
+                            let name = self.get_client(&packet.id)?.read().await.name.clone();
+                            self.emit(ServerEvent::ShineCollected {
+                                name,
+                                shine_id: *shine_id,
+                            });
+                        }
+                        // Moons are always recorded, so they can be synced
+                        // once sync is back on or the collector is included
+                        if !self.is_shine_sync_excluded(&packet.id).await {
+                            self.sync_all_shines().await?;
+                        }
 
                         return Ok(true);
                     }
@@ -68,37 +165,33 @@ impl Coordinator {
                         scenario_num,
                         stage,
                     } => {
-                        if stage == "CapWorldHomeStage" && *scenario_num == 0 {
-                            let client = self.get_client(&packet.id)?;
-                            let mut data = client.write().await;
-                            tracing::info!("Player '{}' starting speedrun", data.name);
-                            data.speedrun_start = true;
-                            data.shine_sync.clear();
-                            drop(data);
-                            self.shine_bag.write().await.clear();
-                            self.persist_shines().await;
-                        } else if stage == "WaterfallWordHomeStage" {
-                            let client = self.get_client(&packet.id)?;
-                            let mut data = client.write().await;
-                            tracing::info!("Enabling shine sync for player '{}'", data.name);
-                            let was_speed_run = data.speedrun_start;
-                            data.speedrun_start = false;
-                            drop(data);
-
-                            if was_speed_run {
-                                let client = client.clone();
-                                let channel = self.get_channel(&packet.id)?.clone();
-                                let shine_bag = self.shine_bag.clone();
-                                tokio::spawn(async move {
-                                    tokio::time::sleep(Duration::from_secs(15)).await;
-
-                                    let result =
-                                        client_sync_shines(channel, shine_bag, &packet.id, &client)
-                                            .await;
-                                    if let Err(e) = result {
-                                        tracing::warn!("Initial shine sync failed: {e}")
-                                    }
-                                });
+                        self.update_shine_sync_state(&packet.id, stage, *scenario_num)
+                            .await?;
+
+                        if self.settings.read().await.scenario.merge_enabled {
+                            self.broadcast_merged_scenario(packet).await?;
+                            return Ok(true);
+                        }
+                    }
+                    PacketData::Tag { .. } if self.tag_game.is_active() => {
+                        self.handle_tag_packet(packet).await?;
+                        return Ok(true);
+                    }
+                    PacketData::Tag {
+                        update_type,
+                        is_it,
+                        seconds,
+                        minutes,
+                    } => {
+                        // Without a server run game clients keep their own tag state
+                        let mut data = self.get_client(&packet.id)?.write().await;
+                        match update_type {
+                            TagUpdate::Time => {
+                                data.time =
+                                    Duration::from_secs(*seconds as u64 + *minutes as u64 * 60);
+                            }
+                            TagUpdate::State => {
+                                data.is_seeking = *is_it;
                             }
                         }
                     }
@@ -106,14 +199,680 @@ impl Coordinator {
                 };
                 self.broadcast(packet).await?;
             }
-            Command::Cli(_) => todo!(),
+            Command::Cli(cmd, reply) => {
+                let result = self.handle_cli_command(cmd).await;
+                let _ = reply.send(result);
+            }
+            Command::Client(_) => {
+                tracing::warn!("Coordinator received a client only command");
+            }
         }
         Ok(true)
     }
 
+    async fn handle_cli_command(&mut self, cmd: CliCommand) -> Result<String> {
+        match cmd {
+            CliCommand::SendAll { stage } => {
+                let stage = input_to_stage(&stage).ok_or(SMOError::InvalidStage(stage))?;
+                let packet = Packet::new(
+                    Guid::default(),
+                    PacketData::ChangeStage {
+                        stage: stage.clone(),
+                        id: "".to_string(),
+                        scenerio: -1,
+                        sub_scenario: 0,
+                    },
+                );
+                for channel in self.to_clients.values() {
+                    channel.send(Command::Packet(packet.clone())).await?;
+                }
+                Ok(format!("Sent players to {}:-1", stage))
+            }
+            CliCommand::Send {
+                stage,
+                id,
+                scenario,
+                players,
+            } => {
+                let stage = input_to_stage(&stage).ok_or(SMOError::InvalidStage(stage))?;
+                if scenario < -1 {
+                    return Err(SMOError::InvalidScenario(scenario));
+                }
+                let guids = self.get_player_guids(&players).await?;
+                let packet = Packet::new(
+                    Guid::default(),
+                    PacketData::ChangeStage {
+                        stage: stage.clone(),
+                        id,
+                        scenerio: scenario,
+                        sub_scenario: 0,
+                    },
+                );
+                for guid in &guids {
+                    self.get_channel(guid)?
+                        .send(Command::Packet(packet.clone()))
+                        .await?;
+                }
+                Ok(format!(
+                    "Sent {} players to {}:{}",
+                    guids.len(),
+                    stage,
+                    scenario
+                ))
+            }
+            CliCommand::Ban { players } => {
+                let guids = self.get_player_guids(&players).await?;
+                let mut ips = Vec::new();
+                let mut names = Vec::new();
+                for guid in &guids {
+                    let data = self.get_client(guid)?.read().await;
+                    ips.extend(data.ip);
+                    names.push(data.name.clone());
+                }
+
+                let mut settings = self.settings.write().await;
+                settings.ban_list.players.extend(guids.iter().copied());
+                settings.ban_list.ips.extend(ips);
+                drop(settings);
+                self.save_settings().await?;
+
+                for guid in &guids {
+                    self.disconnect_player(*guid).await?;
+                    self.disconnected_clients.remove(guid);
+                }
+                for name in names {
+                    self.emit(ServerEvent::PlayerBanned { name });
+                }
+                Ok(format!("Banned {} players", guids.len()))
+            }
+            CliCommand::Crash { players } => {
+                let guids = self.get_player_guids(&players).await?;
+                let packet = Packet::new(
+                    Guid::default(),
+                    PacketData::ChangeStage {
+                        stage: "$agogusStage".to_string(),
+                        id: "$among$us/SubArea".to_string(),
+                        scenerio: 21,
+                        sub_scenario: 69,
+                    },
+                );
+                for guid in &guids {
+                    self.get_channel(guid)?
+                        .send(Command::Packet(packet.clone()))
+                        .await?;
+                    self.disconnect_player(*guid).await?;
+                }
+                Ok(format!("Crashed {} players", guids.len()))
+            }
+            CliCommand::Rejoin { players } => {
+                // Dropping the connection makes the game reconnect on its own,
+                // and the kept client data is picked up again on reconnect
+                let guids = self.get_player_guids(&players).await?;
+                for guid in &guids {
+                    self.disconnect_player(*guid).await?;
+                }
+                Ok(format!("Rejoined {} players", guids.len()))
+            }
+            CliCommand::Say { message } => {
+                let message = message.join(" ");
+                if message.len() > SERVER_MESSAGE_SIZE {
+                    return Err(SMOError::MessageTooLong(message.len()));
+                }
+
+                let packet = Packet::new(Guid::default(), PacketData::ServerMessage { message });
+                let mut sent = 0;
+                for (guid, client) in &self.clients {
+                    let extensions = client.read().await.extensions;
+                    if !extensions.contains(Extensions::SERVER_MESSAGE) {
+                        continue;
+                    }
+                    if let Some(channel) = self.to_clients.get(guid) {
+                        channel.send(Command::Packet(packet.clone())).await?;
+                        sent += 1;
+                    }
+                }
+                Ok(format!("Sent message to {} players", sent))
+            }
+            CliCommand::Scenario(ScenarioCommand::Merge { enabled }) => {
+                let mut settings = self.settings.write().await;
+                if let Some(enabled) = enabled {
+                    settings.scenario.merge_enabled = enabled;
+                }
+                let state = if settings.scenario.merge_enabled {
+                    "enabled"
+                } else {
+                    "disabled"
+                };
+                drop(settings);
+
+                if enabled.is_some() {
+                    self.save_settings().await?;
+                }
+                Ok(format!("Scenario merge {}", state))
+            }
+            CliCommand::Tag(tag) => self.handle_tag_command(tag).await,
+            CliCommand::MaxPlayers { player_count } => {
+                let mut settings = self.settings.write().await;
+                settings.server.max_players = player_count;
+                drop(settings);
+                self.save_settings().await?;
+
+                self.broadcast_max_players(player_count).await?;
+                Ok(format!("Set max players to {}", player_count))
+            }
+            CliCommand::List { json } => {
+                let players = self.player_statuses().await;
+                if json {
+                    Ok(serde_json::to_string_pretty(&players)?)
+                } else {
+                    let table = if players.is_empty() {
+                        "No players connected".to_string()
+                    } else {
+                        format_table(&players)
+                    };
+                    Ok(format!("{}\nUdp datagrams: {}", table, self.udp_stats))
+                }
+            }
+            CliCommand::Flip(flip) => self.handle_flip_command(flip).await,
+            CliCommand::Shine(shine) => self.handle_shine_command(shine).await,
+            CliCommand::LoadSettings => self.reload_settings().await,
+        }
+    }
+
+    async fn player_statuses(&self) -> Vec<PlayerStatus> {
+        let mut players = Vec::new();
+        for (guid, client) in &self.clients {
+            let data = client.read().await;
+            let (stage, scenario) = match &data.last_game_packet {
+                Some(Packet {
+                    data:
+                        PacketData::Game {
+                            stage,
+                            scenario_num,
+                            ..
+                        },
+                    ..
+                }) => (Some(stage.clone()), Some(*scenario_num)),
+                _ => (None, None),
+            };
+            let tag = self.tag_game.is_seeking(guid).map(|is_seeking| TagStatus {
+                is_seeking,
+                seconds: self.tag_game.time(guid).unwrap_or_default().as_secs(),
+            });
+
+            players.push(PlayerStatus {
+                guid: guid.to_string(),
+                name: data.name.clone(),
+                ip: data.ip,
+                mode: data.mode,
+                stage,
+                scenario,
+                body: data.costume.body_name.clone(),
+                cap: data.costume.cap_name.clone(),
+                capture: data.capture.clone(),
+                tag,
+                connected_seconds: data
+                    .connected_at
+                    .map(|time| time.elapsed().as_secs())
+                    .unwrap_or_default(),
+            });
+        }
+        players.sort_by(|a, b| a.name.cmp(&b.name));
+        players
+    }
+
+    /// Writes the current settings to disk without holding the settings lock
+    /// during the file io
+    async fn save_settings(&self) -> Result<()> {
+        let settings = self.settings.read().await.clone();
+        save_settings(&settings)
+    }
+
+    /// Reads settings.json again and applies what changed to the running server.
+    /// The current settings are kept if the file can't be read or parsed.
+    async fn reload_settings(&mut self) -> Result<String> {
+        let new_settings = read_settings()?;
+        let old_settings =
+            std::mem::replace(&mut *self.settings.write().await, new_settings.clone());
+
+        let mut changes = Vec::new();
+        if old_settings.server.address != new_settings.server.address
+            || old_settings.server.port != new_settings.server.port
+        {
+            tracing::warn!("Server address changes require a restart");
+        }
+        if old_settings.server.max_players != new_settings.server.max_players {
+            changes.push(format!(
+                "max players {} -> {}",
+                old_settings.server.max_players, new_settings.server.max_players
+            ));
+            self.broadcast_max_players(new_settings.server.max_players)
+                .await?;
+        }
+        if old_settings.flip.enabled != new_settings.flip.enabled
+            || old_settings.flip.players != new_settings.flip.players
+        {
+            changes.push("flip players updated".to_string());
+        }
+        if old_settings.scenario.merge_enabled != new_settings.scenario.merge_enabled {
+            changes.push(format!(
+                "scenario merge {}",
+                new_settings.scenario.merge_enabled
+            ));
+        }
+        if old_settings.shine_sync != new_settings.shine_sync {
+            changes.push("shine sync updated".to_string());
+            // Players who are included now may be missing moons
+            self.sync_all_shines().await?;
+        }
+
+        let ban_list = &new_settings.ban_list;
+        let mut banned = Vec::new();
+        for (guid, client) in &self.clients {
+            let ip = client.read().await.ip;
+            let ip_banned = ip.is_some_and(|ip| ban_list.ips.contains(&ip));
+            if ban_list.players.contains(guid) || ip_banned {
+                banned.push(*guid);
+            }
+        }
+        for guid in &banned {
+            tracing::info!("Kicking newly banned player {}", guid);
+            self.disconnect_player(*guid).await?;
+            self.disconnected_clients.remove(guid);
+        }
+        if !banned.is_empty() {
+            changes.push(format!("kicked {} banned players", banned.len()));
+        }
+
+        if changes.is_empty() {
+            Ok("Loaded settings.json".to_string())
+        } else {
+            Ok(format!("Loaded settings.json: {}", changes.join(", ")))
+        }
+    }
+
+    async fn handle_tag_command(&mut self, cmd: TagCommand) -> Result<String> {
+        match cmd {
+            TagCommand::Time {
+                player,
+                minutes,
+                seconds,
+            } => {
+                let time = Duration::from_secs(seconds as u64 + minutes as u64 * 60);
+                let guids = self.get_player_guids(&[player]).await?;
+                for guid in &guids {
+                    self.get_client(guid)?.write().await.time = time;
+                    if self.tag_game.is_active() {
+                        self.tag_game.set_time(*guid, time);
+                    }
+                    self.send_tag_update(tag_time_packet(*guid, time)).await?;
+                }
+                Ok(format!(
+                    "Set time for {} players to {}",
+                    guids.len(),
+                    format_tag_time(time)
+                ))
+            }
+            TagCommand::Seeking { player, is_seeking } => {
+                let guids = self.get_player_guids(&[player]).await?;
+                for guid in &guids {
+                    self.get_client(guid)?.write().await.is_seeking = is_seeking;
+                    if self.tag_game.is_active() {
+                        self.tag_game.set_seeking(*guid, is_seeking);
+                    }
+                    self.send_tag_update(tag_state_packet(*guid, is_seeking))
+                        .await?;
+                }
+                let role = if is_seeking { "seeking" } else { "hiding" };
+                let response = format!("Set {} players to {}", guids.len(), role);
+                if self.tag_game.is_finished() {
+                    let result = self.finish_tag_game().await;
+                    return Ok(format!("{}\n{}", response, result));
+                }
+                Ok(response)
+            }
+            TagCommand::Start { countdown, seekers } => {
+                let seekers: HashSet<Guid> =
+                    self.get_player_guids(&seekers).await?.into_iter().collect();
+                if seekers.is_empty() {
+                    return Err(SMOError::NoSeekers);
+                }
+                let players: Vec<Guid> = self.clients.keys().copied().collect();
+                let countdown_time = Duration::from_secs(countdown.into());
+                self.tag_game
+                    .start(players.clone(), &seekers, countdown_time, Instant::now());
+
+                for guid in players {
+                    let is_seeking = seekers.contains(&guid);
+                    let client = self.get_client(&guid)?;
+                    let mut data = client.write().await;
+                    data.is_seeking = is_seeking;
+                    data.time = Duration::ZERO;
+                    drop(data);
+
+                    self.send_tag_update(tag_state_packet(guid, is_seeking))
+                        .await?;
+                    if !is_seeking {
+                        self.send_tag_update(tag_time_packet(guid, Duration::ZERO))
+                            .await?;
+                    }
+                }
+
+                Ok(format!(
+                    "Starting game in {} seconds with {} seekers",
+                    countdown,
+                    seekers.len()
+                ))
+            }
+            TagCommand::Stop => {
+                if !self.tag_game.is_active() {
+                    return Ok("No tag game running".to_string());
+                }
+                Ok(self.finish_tag_game().await)
+            }
+        }
+    }
+
+    /// Filters tag updates from players in the current game, so hiding time
+    /// and roles are decided by the server rather than reported by clients
+    async fn handle_tag_packet(&mut self, packet: Packet) -> Result<()> {
+        let (update_type, is_it) = match packet.data {
+            PacketData::Tag {
+                update_type, is_it, ..
+            } => (update_type, is_it),
+            _ => unreachable!(),
+        };
+
+        let is_seeking = match self.tag_game.is_seeking(&packet.id) {
+            Some(is_seeking) => is_seeking,
+            None => return self.broadcast(packet).await,
+        };
+
+        match update_type {
+            TagUpdate::Time => Ok(()),
+            TagUpdate::State if is_it == is_seeking => self.broadcast(packet).await,
+            TagUpdate::State if is_it && self.tag_game.is_running() => {
+                self.tag_game.catch(&packet.id);
+                let client = self.get_client(&packet.id)?;
+                let mut data = client.write().await;
+                data.is_seeking = true;
+                let name = data.name.clone();
+                drop(data);
+
+                let time = self.tag_game.time(&packet.id).unwrap_or_default();
+                tracing::info!("{} was caught after {}", name, format_tag_time(time));
+                self.broadcast(packet).await?;
+
+                if self.tag_game.is_finished() {
+                    self.finish_tag_game().await;
+                }
+                Ok(())
+            }
+            TagUpdate::State => {
+                // Put the client back into the role the server has for it
+                let correction =
+                    ClientCommand::SelfAddressed(tag_state_packet(packet.id, is_seeking));
+                self.get_channel(&packet.id)?
+                    .send(Command::Client(correction))
+                    .await?;
+                Ok(())
+            }
+        }
+    }
+
+    async fn update_tag_game(&mut self) -> Result<()> {
+        let was_running = self.tag_game.is_running();
+        self.tag_game.update(Instant::now());
+        if !self.tag_game.is_running() {
+            return Ok(());
+        }
+        if !was_running {
+            tracing::info!("Tag game started");
+        }
+
+        let hider_times: Vec<_> = self.tag_game.hider_times().collect();
+        for (guid, time) in hider_times {
+            if let Some(client) = self.clients.get(&guid) {
+                client.write().await.time = time;
+            }
+            self.send_tag_update(tag_time_packet(guid, time)).await?;
+        }
+
+        if self.tag_game.is_finished() {
+            self.finish_tag_game().await;
+        }
+        Ok(())
+    }
+
+    /// Ends the game and announces the winner, returning the announcement
+    async fn finish_tag_game(&mut self) -> String {
+        let winner = self.tag_game.winner();
+        self.tag_game.stop();
+        let event = match winner {
+            Some((guid, time)) => {
+                let name = match self.clients.get(&guid) {
+                    Some(client) => client.read().await.name.clone(),
+                    None => guid.to_string(),
+                };
+                ServerEvent::TagGameWon { name, time }
+            }
+            None => ServerEvent::TagGameOver,
+        };
+        let result = event.to_string();
+        tracing::info!("{}", result);
+        self.emit(event);
+        result
+    }
+
+    async fn handle_flip_command(&mut self, cmd: FlipCommand) -> Result<String> {
+        let mut settings = self.settings.write().await;
+        let flip = &mut settings.flip;
+        let response = match cmd {
+            FlipCommand::List => {
+                let players: Vec<String> = flip.players.iter().map(|p| p.to_string()).collect();
+                return Ok(format!("Flipped players: {}", players.join(", ")));
+            }
+            FlipCommand::Add { player } => {
+                flip.players.insert(player);
+                format!("Added {} to flipped players", player)
+            }
+            FlipCommand::Remove { player } => {
+                flip.players.remove(&player);
+                format!("Removed {} from flipped players", player)
+            }
+            FlipCommand::Set { is_flipped } => {
+                flip.enabled = is_flipped;
+                let state = if is_flipped { "enabled" } else { "disabled" };
+                format!("Flipping {}", state)
+            }
+            FlipCommand::Pov { value } => {
+                flip.pov = value.into();
+                format!("Set flip pov to {:?}", flip.pov)
+            }
+        };
+        drop(settings);
+
+        self.save_settings().await?;
+        Ok(response)
+    }
+
+    async fn handle_shine_command(&mut self, cmd: ShineCommand) -> Result<String> {
+        match cmd {
+            ShineCommand::List => {
+                let shine_bag = self.shine_bag.read().await;
+                if shine_bag.is_empty() {
+                    return Ok("Shine bag is empty".to_string());
+                }
+
+                let mut shines: Vec<_> = shine_bag.values().collect();
+                shines.sort_by_key(|shine| shine.id);
+                let mut lines = vec![format!("{} shines:", shines.len())];
+                for shine in shines {
+                    let collector = match &shine.collector {
+                        Some(guid) => match self.player_name(guid).await {
+                            Some(name) => format!("{} ({})", name, guid),
+                            None => guid.to_string(),
+                        },
+                        None => "unknown".to_string(),
+                    };
+                    let collected = shine
+                        .collected_at
+                        .and_then(|time| time.elapsed().ok())
+                        .map(|elapsed| format!(", {} ago", format_duration(elapsed.as_secs())))
+                        .unwrap_or_default();
+                    let grand = if shine.is_grand { " (grand)" } else { "" };
+                    let stage = match &shine.stage {
+                        Some(stage) => format!(" in {}", stage),
+                        None => String::new(),
+                    };
+                    lines.push(format!(
+                        "{}{}: {}{}{}",
+                        shine.id, grand, collector, stage, collected
+                    ));
+                }
+                Ok(lines.join("\n"))
+            }
+            ShineCommand::Clear => {
+                self.shine_bag.write().await.clear();
+                for client in self.clients.values() {
+                    client.write().await.shine_sync.clear();
+                }
+                self.persist_shines().await;
+                Ok("Cleared shine bags".to_string())
+            }
+            ShineCommand::Sync => {
+                self.sync_all_shines().await?;
+                Ok("Synced shine bags".to_string())
+            }
+            ShineCommand::Exclude { players } => self.set_shine_sync_excluded(&players, true).await,
+            ShineCommand::Include { players } => {
+                self.set_shine_sync_excluded(&players, false).await
+            }
+            ShineCommand::Send { id, players } => {
+                let shine_id: i32 = id.try_into().map_err(EncodingError::from)?;
+                let guids = self.get_player_guids(&players).await?;
+                let is_grand = self
+                    .shine_bag
+                    .read()
+                    .await
+                    .get(&shine_id)
+                    .map(|shine| shine.is_grand)
+                    .unwrap_or_default();
+                let packet = Packet::new(Guid::default(), PacketData::Shine { shine_id, is_grand });
+                for guid in &guids {
+                    self.get_client(guid)?
+                        .write()
+                        .await
+                        .shine_sync
+                        .insert(shine_id);
+                    self.get_channel(guid)?
+                        .send(Command::Packet(packet.clone()))
+                        .await?;
+                }
+                Ok(format!(
+                    "Sent shine {} to {} players",
+                    shine_id,
+                    guids.len()
+                ))
+            }
+        }
+    }
+
+    /// Adds players to or removes them from the shine sync exclusion list
+    async fn set_shine_sync_excluded(
+        &mut self,
+        players: &[PlayerSelect],
+        exclude: bool,
+    ) -> Result<String> {
+        let guids = self.get_player_guids(players).await?;
+        let mut settings = self.settings.write().await;
+        let excluded = &mut settings.shine_sync.excluded_players;
+        for guid in &guids {
+            if exclude {
+                excluded.insert(*guid);
+            } else {
+                excluded.remove(guid);
+            }
+        }
+        drop(settings);
+        self.save_settings().await?;
+
+        if exclude {
+            Ok(format!("Excluded {} players from shine sync", guids.len()))
+        } else {
+            self.sync_all_shines().await?;
+            Ok(format!("Included {} players in shine sync", guids.len()))
+        }
+    }
+
+    /// Informs a player of their own tag state and relays it to everyone else
+    async fn send_tag_update(&mut self, packet: Packet) -> Result<()> {
+        let self_packet = ClientCommand::SelfAddressed(packet.clone());
+        self.get_channel(&packet.id)?
+            .send(Command::Client(self_packet))
+            .await?;
+        self.broadcast(packet).await
+    }
+
+    async fn get_player_guids(&self, players: &[PlayerSelect]) -> Result<Vec<Guid>> {
+        let mut guids = Vec::new();
+        for select in players {
+            match select {
+                PlayerSelect::AllPlayers => return Ok(self.clients.keys().copied().collect()),
+                PlayerSelect::Player(name) => {
+                    let guid = self
+                        .find_player(name)
+                        .await
+                        .ok_or_else(|| SMOError::PlayerNotFound(name.clone()))?;
+                    if !guids.contains(&guid) {
+                        guids.push(guid);
+                    }
+                }
+            }
+        }
+        Ok(guids)
+    }
+
+    /// Name of a connected or previously connected player
+    async fn player_name(&self, guid: &Guid) -> Option<String> {
+        let client = self
+            .clients
+            .get(guid)
+            .or_else(|| self.disconnected_clients.get(guid))?;
+        Some(client.read().await.name.clone())
+    }
+
+    /// Looks a connected player up by guid, falling back to their name
+    async fn find_player(&self, name: &str) -> Option<Guid> {
+        if let Ok(guid) = name.parse::<Guid>() {
+            if self.clients.contains_key(&guid) {
+                return Some(guid);
+            }
+        }
+
+        for (guid, client) in &self.clients {
+            if client.read().await.name == name {
+                return Some(*guid);
+            }
+        }
+        None
+    }
+
     async fn persist_shines(&self) {
-        // TODO
-        tracing::warn!("Shine persisting not avaliable.")
+        let settings = self.settings.read().await;
+        if !settings.persist_shines.enabled {
+            return;
+        }
+        let filename = settings.persist_shines.filename.clone();
+        drop(settings);
+
+        let shines = self.shine_bag.read().await.clone();
+        let result = tokio::task::spawn_blocking(move || save_shines(&filename, &shines)).await;
+        match result {
+            Ok(Ok(())) => tracing::debug!("Persisted shines"),
+            Ok(Err(e)) => tracing::warn!("Failed to persist shines: {e}"),
+            Err(e) => tracing::warn!("Failed to persist shines: {e}"),
+        }
     }
 
     fn get_client(&self, id: &Guid) -> std::result::Result<&SyncClient, SMOError> {
@@ -134,12 +893,8 @@ impl Coordinator {
             _ => unreachable!(),
         };
 
-        let (connection_type, client_name) = match &packet.data {
-            PacketData::Connect {
-                c_type,
-                client_name,
-                ..
-            } => (c_type, client_name),
+        let connection_type = match &packet.data {
+            PacketData::Connect { c_type, .. } => c_type,
             _ => unreachable!(),
         };
 
@@ -150,12 +905,15 @@ impl Coordinator {
             let banned_players = &settings.ban_list.players;
             let banned_ips = &settings.ban_list.ips;
 
-            if max_players < self.clients.len() {
-                tracing::warn!(
-                    "Reached max players: {} <= {}",
-                    max_players,
-                    self.clients.len()
-                );
+            // A reconnecting player replaces their own old connection
+            let connected = self
+                .to_clients
+                .iter()
+                .filter(|(guid, comm)| **guid != cli.guid && !comm.is_closed())
+                .count();
+
+            if connected >= max_players {
+                tracing::warn!("Reached max players: {} >= {}", connected, max_players);
                 Err(SMOError::ClientInit(ClientInitError::TooManyPlayers))
             } else if banned_players.contains(&cli.guid) {
                 Err(SMOError::ClientInit(ClientInitError::BannedID))
@@ -174,22 +932,38 @@ impl Coordinator {
         let id = cli.guid;
         match connection_type {
             ConnectionType::FirstConnection => {
+                self.disconnected_clients.remove(&id);
                 self.clients.insert(id, cli.data.clone());
             }
-            ConnectionType::Reconnecting => match self.clients.get(&id) {
-                Some(prev_data) => {
-                    cli.data = prev_data.clone();
-                }
-                None => {
-                    self.clients.insert(id, cli.data.clone());
+            ConnectionType::Reconnecting => {
+                let prev_data = self
+                    .clients
+                    .get(&id)
+                    .cloned()
+                    .or_else(|| self.disconnected_clients.restore(&id, Instant::now()));
+                match prev_data {
+                    Some(prev_data) => {
+                        tracing::debug!("Restoring data for reconnecting player {}", id);
+                        let mut data = prev_data.write().await;
+                        data.ip = Some(cli.conn.addr.ip());
+                        data.connected_at = Some(Instant::now());
+                        data.mode = ConnectionMode::Tcp;
+                        data.extensions = cli.extensions;
+                        drop(data);
+                        cli.data = prev_data.clone();
+                        self.clients.insert(id, prev_data);
+                    }
+                    None => {
+                        self.clients.insert(id, cli.data.clone());
+                    }
                 }
-            },
+            }
         }
         self.to_clients.insert(id, comm.clone());
 
         let name = cli.display_name.clone();
         tracing::info!("New client connected: {} ({})", &name, cli.guid);
-        let span = info_span!("client", name);
+        let span = info_span!("client", name = name.as_str());
         tokio::spawn(async move { cli.handle_events().await }.instrument(span));
 
         let result = self.setup_player(comm, *packet).await;
@@ -197,6 +971,7 @@ impl Coordinator {
             self.disconnect_player(id).await?;
             return Err(e);
         }
+        self.emit(ServerEvent::PlayerJoined { name });
         Ok(())
     }
 
@@ -220,6 +995,7 @@ impl Coordinator {
                     c_type: ConnectionType::FirstConnection,
                     max_player,
                     client_name: other_cli.name.clone(),
+                    protocol: None,
                 },
             );
 
@@ -243,7 +1019,17 @@ impl Coordinator {
 
     async fn disconnect_player(&mut self, guid: Guid) -> Result<()> {
         tracing::info!("Disconnecting player {}", guid);
-        self.clients.remove(&guid);
+        if let Some(data) = self.clients.remove(&guid) {
+            let name = data.read().await.name.clone();
+            self.disconnected_clients.insert(guid, data, Instant::now());
+            self.emit(ServerEvent::PlayerLeft { name });
+        }
+        if self.tag_game.is_active() {
+            self.tag_game.remove_player(&guid);
+            if self.tag_game.is_finished() {
+                self.finish_tag_game().await;
+            }
+        }
         if let Some(comm) = self.to_clients.remove(&guid) {
             let packet = Packet::new(guid, PacketData::Disconnect);
             self.broadcast(packet.clone()).await?;
@@ -254,14 +1040,33 @@ impl Coordinator {
         Ok(())
     }
 
+    /// Resends the init packet so connected clients pick up a new player limit
+    async fn broadcast_max_players(&mut self, max_players: u16) -> Result<()> {
+        // Agreed protocols stay as they are when the init leaves them out
+        let init = Packet::new(
+            Guid::default(),
+            PacketData::Init {
+                max_players,
+                protocol: None,
+            },
+        );
+        self.broadcast(init).await
+    }
+
+    fn emit(&self, event: ServerEvent) {
+        // Nobody listening for events is fine
+        let _ = self.events.send(event);
+    }
+
     async fn sync_all_shines(&mut self) -> Result<()> {
+        let policy = self.settings.read().await.shine_sync.clone();
         for (guid, client) in &self.clients {
             let channel = self.to_clients.get(guid).unwrap();
-            let sender_guid = Guid::default();
             client_sync_shines(
                 channel.clone(),
                 self.shine_bag.clone(),
-                &sender_guid,
+                &policy,
+                guid,
                 client,
             )
             .await?;
@@ -269,14 +1074,146 @@ impl Coordinator {
         Ok(())
     }
 
+    async fn is_shine_sync_excluded(&self, guid: &Guid) -> bool {
+        let settings = self.settings.read().await;
+        !settings.shine_sync.enabled || settings.shine_sync.excluded_players.contains(guid)
+    }
+
+    /// Moves the player's shine sync state along after a stage change
+    async fn update_shine_sync_state(
+        &mut self,
+        guid: &Guid,
+        stage: &str,
+        scenario: u8,
+    ) -> Result<()> {
+        let client = self.get_client(guid)?.clone();
+        let mut data = client.write().await;
+        let old_state = data.shine_sync_state;
+        let new_state = old_state.on_stage(stage, scenario, Instant::now());
+        if new_state == old_state {
+            return Ok(());
+        }
+        data.shine_sync_state = new_state;
+
+        match new_state {
+            ShineSyncState::NewSave => {
+                tracing::info!(
+                    "Player '{}' started a new save, pausing shine sync",
+                    data.name
+                );
+                data.shine_sync.clear();
+                drop(data);
+                self.shine_bag.write().await.clear();
+                self.persist_shines().await;
+            }
+            ShineSyncState::Resuming { .. } => {
+                tracing::info!("Resuming shine sync for player '{}'", data.name);
+                drop(data);
+
+                let channel = self.get_channel(guid)?.clone();
+                let shine_bag = self.shine_bag.clone();
+                let settings = self.settings.clone();
+                let guid = *guid;
+                tokio::spawn(async move {
+                    tokio::time::sleep(RESUME_SYNC_DELAY).await;
+
+                    let policy = settings.read().await.shine_sync.clone();
+                    let result =
+                        client_sync_shines(channel, shine_bag, &policy, &guid, &client).await;
+                    if let Err(e) = result {
+                        tracing::warn!("Initial shine sync failed: {e}")
+                    }
+                });
+            }
+            ShineSyncState::Syncing => {}
+        }
+        Ok(())
+    }
+
     async fn broadcast(&mut self, mut p: Packet) -> Result<()> {
         p.resize();
+        if let PacketData::Player { .. } | PacketData::Cap { .. } = p.data {
+            let settings = self.settings.read().await;
+            if settings.flip.enabled && !settings.flip.players.is_empty() {
+                let flip = settings.flip.clone();
+                drop(settings);
+                return self.broadcast_flipped(p, &flip).await;
+            }
+        }
+
         for cli in &mut self.to_clients.values() {
             cli.send(Command::Packet(p.clone())).await?;
         }
         Ok(())
     }
 
+    /// Forwards a packet nobody needs to look at. Every client gets a handle
+    /// to the same frame, only flipped players need it decoded.
+    async fn relay(&mut self, frame: RawPacket) -> Result<()> {
+        let settings = self.settings.read().await;
+        let flipped = settings.flip.enabled && !settings.flip.players.is_empty();
+        drop(settings);
+        if flipped && !frame.needs_decode() {
+            return self.broadcast(frame.decode()?).await;
+        }
+
+        for cli in self.to_clients.values() {
+            cli.send(Command::Relay(frame.clone())).await?;
+        }
+        Ok(())
+    }
+
+    /// Relays a game packet with the scenario replaced by each recipient's own,
+    /// so players in different scenarios of a kingdom still see each other
+    async fn broadcast_merged_scenario(&mut self, mut p: Packet) -> Result<()> {
+        p.resize();
+        for (guid, cli) in &self.to_clients {
+            let recipient_scenario = match self.clients.get(guid) {
+                Some(client) => {
+                    let data = client.read().await;
+                    if data.last_game_packet.is_some() {
+                        data.scenario
+                    } else {
+                        UNKNOWN_SCENARIO
+                    }
+                }
+                None => UNKNOWN_SCENARIO,
+            };
+
+            let mut packet = p.clone();
+            if let PacketData::Game { scenario_num, .. } = &mut packet.data {
+                *scenario_num = recipient_scenario;
+            }
+            cli.send(Command::Packet(packet)).await?;
+        }
+        Ok(())
+    }
+
+    /// Broadcasts a movement packet, turning it upside down for the recipients
+    /// that should see the sender flipped
+    async fn broadcast_flipped(&mut self, p: Packet, flip: &FlipSettings) -> Result<()> {
+        let is_2d = match self.clients.get(&p.id) {
+            Some(client) => client.read().await.is_2d,
+            None => false,
+        };
+        let mut flipped = p.clone();
+        flip_packet(&mut flipped, is_2d);
+
+        let sender_flipped = flip.players.contains(&p.id);
+        for (guid, cli) in &self.to_clients {
+            let should_flip = if sender_flipped {
+                matches!(flip.pov, FlipPovSettings::Both | FlipPovSettings::Others)
+            } else {
+                flip.players.contains(guid)
+                    && matches!(flip.pov, FlipPovSettings::Both | FlipPovSettings::Player)
+            };
+
+            let packet = if should_flip { &flipped } else { &p };
+            cli.send(Command::Packet(packet.clone())).await?;
+        }
+        Ok(())
+    }
+
     async fn shutdown(mut self) {
         let active_clients = self.to_clients.clone();
         for guid in active_clients.keys() {
@@ -285,30 +1222,87 @@ impl Coordinator {
     }
 }
 
+/// Sends a player every moon in the shine bag they don't have yet, as far as
+/// the shine sync policy allows
 async fn client_sync_shines(
     to_client: mpsc::Sender<Command>,
     shine_bag: SyncShineBag,
+    policy: &ShineSyncSettings,
     guid: &Guid,
     client: &SyncClient,
 ) -> Result<()> {
     let client = client.read().await;
-    if client.speedrun_start {
+    if !policy.enabled
+        || policy.excluded_players.contains(guid)
+        || !client.shine_sync_state.can_sync(Instant::now())
+    {
         return Ok(());
     }
 
     let server_shines = shine_bag.read().await;
-    let mismatch = server_shines.difference(&client.shine_sync);
+    let mismatch = server_shines.values().filter(|shine| {
+        let collector_excluded = shine
+            .collector
+            .map(|collector| policy.excluded_players.contains(&collector))
+            .unwrap_or(false);
+        !client.shine_sync.contains(&shine.id)
+            && !policy.excluded_shines.contains(&shine.id)
+            && !collector_excluded
+    });
 
-    for shine_id in mismatch {
+    for shine in mismatch {
         to_client
             .send(Command::Packet(Packet::new(
-                *guid,
+                Guid::default(),
                 PacketData::Shine {
-                    shine_id: *shine_id,
-                    is_grand: false,
+                    shine_id: shine.id,
+                    is_grand: shine.is_grand,
                 },
             )))
             .await?;
     }
     Ok(())
 }
+
+/// Turns a player or cap upside down around its forward axis, shifting it up
+/// by Mario's height so the flipped model still stands on the ground
+fn flip_packet(packet: &mut Packet, is_2d: bool) {
+    let (pos, rot) = match &mut packet.data {
+        PacketData::Player { pos, rot, .. } | PacketData::Cap { pos, rot, .. } => (pos, rot),
+        _ => return,
+    };
+
+    let mario_size = if is_2d { 180.0 } else { 160.0 };
+    pos.y += mario_size;
+    *rot *= Quaternion::new(0.0, 0.0, 0.0, 1.0);
+}
+
+fn tag_time_packet(guid: Guid, time: Duration) -> Packet {
+    let total_seconds = time.as_secs();
+    Packet::new(
+        guid,
+        PacketData::Tag {
+            update_type: TagUpdate::Time,
+            is_it: false,
+            seconds: (total_seconds % 60) as u8,
+            minutes: (total_seconds / 60).try_into().unwrap_or(u16::MAX),
+        },
+    )
+}
+
+fn format_tag_time(time: Duration) -> String {
+    let total_seconds = time.as_secs();
+    format!("{}:{:02}", total_seconds / 60, total_seconds % 60)
+}
+
+fn tag_state_packet(guid: Guid, is_seeking: bool) -> Packet {
+    Packet::new(
+        guid,
+        PacketData::Tag {
+            update_type: TagUpdate::State,
+            is_it: is_seeking,
+            seconds: 0,
+            minutes: 0,
+        },
+    )
+}
@@ -0,0 +1,286 @@
+use std::{collections::HashSet, time::Duration};
+
+use clap::Parser;
+use futures::{SinkExt, StreamExt};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+use crate::{
+    cmds::{Cli, Command},
+    coordinator::ServerEvent,
+    settings::DiscordSettings,
+    types::{Result, SMOError},
+};
+
+pub const DEFAULT_GATEWAY_URL: &str = "wss://gateway.discord.gg/?v=10&encoding=json";
+pub const DEFAULT_API_URL: &str = "https://discord.com/api/v10";
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const MAX_MESSAGE_LENGTH: usize = 2000;
+// GUILD_MESSAGES | MESSAGE_CONTENT
+const INTENTS: u64 = (1 << 9) | (1 << 15);
+
+const OP_DISPATCH: u8 = 0;
+const OP_HEARTBEAT: u8 = 1;
+const OP_IDENTIFY: u8 = 2;
+const OP_RECONNECT: u8 = 7;
+const OP_INVALID_SESSION: u8 = 9;
+const OP_HELLO: u8 = 10;
+
+#[derive(Debug, Deserialize)]
+struct GatewayPayload {
+    op: u8,
+    #[serde(default)]
+    d: serde_json::Value,
+    s: Option<u64>,
+    t: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MessageCreate {
+    channel_id: String,
+    content: String,
+    author: Author,
+    /// Only sent for messages in a guild
+    #[serde(default)]
+    member: Option<Member>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Author {
+    id: String,
+    #[serde(default)]
+    bot: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct Member {
+    #[serde(default)]
+    roles: Vec<String>,
+}
+
+/// Discord gateway client that runs prefixed console commands sent to the log
+/// channel by admins and mirrors server events into it
+pub struct DiscordBot {
+    token: String,
+    prefix: String,
+    log_channel: Option<String>,
+    admin_users: HashSet<String>,
+    admin_roles: HashSet<String>,
+    gateway_url: String,
+    api_url: String,
+    http: reqwest::Client,
+    to_coord: mpsc::Sender<Command>,
+}
+
+impl DiscordBot {
+    /// Returns `None` when no bot token is configured
+    pub fn new(settings: &DiscordSettings, to_coord: mpsc::Sender<Command>) -> Option<Self> {
+        let token = settings.token.clone().filter(|t| !t.is_empty())?;
+        Some(Self {
+            token,
+            prefix: settings.prefix.clone(),
+            log_channel: settings.log_channel.clone(),
+            admin_users: settings.admin_users.clone(),
+            admin_roles: settings.admin_roles.clone(),
+            gateway_url: DEFAULT_GATEWAY_URL.to_string(),
+            api_url: DEFAULT_API_URL.to_string(),
+            http: reqwest::Client::new(),
+            to_coord,
+        })
+    }
+
+    pub fn with_urls(mut self, gateway_url: impl Into<String>, api_url: impl Into<String>) -> Self {
+        self.gateway_url = gateway_url.into();
+        self.api_url = api_url.into();
+        self
+    }
+
+    pub async fn run(self, mut events: broadcast::Receiver<ServerEvent>) -> Result<()> {
+        loop {
+            match self.run_session(&mut events).await {
+                Ok(()) => tracing::info!("Discord gateway session ended"),
+                Err(e) => tracing::warn!("Discord gateway session failed: {}", e),
+            }
+
+            if self.to_coord.is_closed() {
+                return Ok(());
+            }
+            tokio::time::sleep(RECONNECT_DELAY).await;
+        }
+    }
+
+    async fn run_session(&self, events: &mut broadcast::Receiver<ServerEvent>) -> Result<()> {
+        let (ws, _) = connect_async(self.gateway_url.as_str())
+            .await
+            .map_err(gateway_error)?;
+        let (mut write, mut read) = ws.split();
+
+        let hello = match read.next().await {
+            Some(msg) => parse_payload(msg.map_err(gateway_error)?)?,
+            None => None,
+        };
+        let heartbeat_interval = match hello {
+            Some(p) if p.op == OP_HELLO => p.d["heartbeat_interval"].as_u64().unwrap_or(41250),
+            _ => return Err(SMOError::DiscordGateway("Expected hello".to_string())),
+        };
+
+        let identify = json!({
+            "op": OP_IDENTIFY,
+            "d": {
+                "token": self.token,
+                "intents": INTENTS,
+                "properties": {
+                    "os": std::env::consts::OS,
+                    "browser": "smoo",
+                    "device": "smoo",
+                },
+            },
+        });
+        write
+            .send(Message::Text(identify.to_string()))
+            .await
+            .map_err(gateway_error)?;
+        tracing::info!("Connected to Discord gateway");
+
+        let mut heartbeat = tokio::time::interval(Duration::from_millis(heartbeat_interval));
+        heartbeat.tick().await;
+        let mut sequence: Option<u64> = None;
+
+        loop {
+            tokio::select! {
+                _ = heartbeat.tick() => {
+                    let beat = json!({ "op": OP_HEARTBEAT, "d": sequence });
+                    write.send(Message::Text(beat.to_string())).await.map_err(gateway_error)?;
+                }
+                msg = read.next() => {
+                    let payload = match msg {
+                        Some(msg) => parse_payload(msg.map_err(gateway_error)?)?,
+                        None => return Ok(()),
+                    };
+                    let payload = match payload {
+                        Some(payload) => payload,
+                        None => continue,
+                    };
+                    if payload.s.is_some() {
+                        sequence = payload.s;
+                    }
+
+                    match payload.op {
+                        OP_DISPATCH if payload.t.as_deref() == Some("MESSAGE_CREATE") => {
+                            let message = serde_json::from_value(payload.d)?;
+                            if let Err(e) = self.handle_message(message).await {
+                                tracing::warn!("Failed to handle discord message: {}", e);
+                            }
+                        }
+                        OP_HEARTBEAT => {
+                            let beat = json!({ "op": OP_HEARTBEAT, "d": sequence });
+                            write.send(Message::Text(beat.to_string())).await.map_err(gateway_error)?;
+                        }
+                        OP_RECONNECT | OP_INVALID_SESSION => return Ok(()),
+                        _ => {}
+                    }
+                }
+                event = events.recv() => {
+                    match event {
+                        Ok(event) => {
+                            if let Err(e) = self.log_event(&event).await {
+                                tracing::warn!("Failed to log event to discord: {}", e);
+                            }
+                        }
+                        Err(broadcast::error::RecvError::Lagged(n)) => {
+                            tracing::warn!("Dropped {} events for discord", n);
+                        }
+                        Err(broadcast::error::RecvError::Closed) => return Ok(()),
+                    }
+                }
+            }
+        }
+    }
+
+    async fn handle_message(&self, message: MessageCreate) -> Result<()> {
+        if message.author.bot || self.log_channel.as_ref() != Some(&message.channel_id) {
+            return Ok(());
+        }
+        let input = match message.content.strip_prefix(&self.prefix) {
+            Some(input) if !input.trim().is_empty() => input,
+            _ => return Ok(()),
+        };
+        if !self.is_admin(&message) {
+            tracing::info!(
+                "Ignoring discord command from non-admin {}",
+                message.author.id
+            );
+            return Ok(());
+        }
+
+        let response = match Cli::try_parse_from(input.split_whitespace()) {
+            Ok(cli) => {
+                let (reply, response) = oneshot::channel();
+                self.to_coord.send(Command::Cli(cli.cmd, reply)).await?;
+                match response.await.map_err(|_| SMOError::RecvChannel)? {
+                    Ok(response) => response,
+                    Err(e) => e.to_string(),
+                }
+            }
+            Err(e) => e.to_string(),
+        };
+
+        self.post_message(&message.channel_id, &code_block(&response))
+            .await
+    }
+
+    /// Nobody is an admin until users or roles are configured
+    fn is_admin(&self, message: &MessageCreate) -> bool {
+        self.admin_users.contains(&message.author.id)
+            || message.member.as_ref().is_some_and(|member| {
+                member
+                    .roles
+                    .iter()
+                    .any(|role| self.admin_roles.contains(role))
+            })
+    }
+
+    async fn log_event(&self, event: &ServerEvent) -> Result<()> {
+        match &self.log_channel {
+            Some(channel) => self.post_message(channel, &event.to_string()).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn post_message(&self, channel: &str, content: &str) -> Result<()> {
+        self.http
+            .post(format!("{}/channels/{}/messages", self.api_url, channel))
+            .header("Authorization", format!("Bot {}", self.token))
+            .json(&json!({ "content": content }))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+fn parse_payload(msg: Message) -> Result<Option<GatewayPayload>> {
+    match msg {
+        Message::Text(text) => Ok(Some(serde_json::from_str(&text)?)),
+        Message::Close(frame) => Err(SMOError::DiscordGateway(format!(
+            "Connection closed: {:?}",
+            frame
+        ))),
+        _ => Ok(None),
+    }
+}
+
+fn gateway_error(e: tokio_tungstenite::tungstenite::Error) -> SMOError {
+    SMOError::DiscordGateway(e.to_string())
+}
+
+/// Wraps command output in a code block that fits in a single discord message
+fn code_block(text: &str) -> String {
+    const FENCE: &str = "```";
+    let max_len = MAX_MESSAGE_LENGTH - 2 * (FENCE.len() + 1);
+    let text: String = text.trim_end().chars().take(max_len).collect();
+    format!("{FENCE}\n{text}\n{FENCE}")
+}
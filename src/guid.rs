@@ -1,7 +1,6 @@
 use std::{fmt::Display, str::FromStr};
 
 use hex::FromHex;
-use quickcheck::Arbitrary;
 use serde::{Deserialize, Serialize};
 
 use crate::types::EncodingError;
@@ -15,7 +14,8 @@ impl FromStr for Guid {
     type Err = EncodingError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let id = <[u8; 16]>::from_hex(s)?;
+        let digits: String = s.chars().filter(|c| *c != '-').collect();
+        let id = <[u8; 16]>::from_hex(digits)?;
         Ok(id.into())
     }
 }
@@ -23,9 +23,9 @@ impl FromStr for Guid {
 impl Display for Guid {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         for (i, digit) in self.id.iter().enumerate() {
-            write!(f, "{:x}", digit)?;
+            write!(f, "{:02x}", digit)?;
             match i {
-                4 | 6 | 8 | 10 => write!(f, "-")?,
+                3 | 5 | 7 | 9 => write!(f, "-")?,
                 _ => {}
             }
         }
@@ -1,8 +1,15 @@
 pub mod client;
 pub mod cmds;
 pub mod coordinator;
+#[cfg(feature = "discord")]
+pub mod discord;
 pub mod guid;
 pub mod net;
+pub mod persist;
 pub mod server;
 pub mod settings;
+pub mod shine;
+pub mod stages;
+pub mod status;
+pub mod tag;
 pub mod types;
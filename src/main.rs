@@ -1,100 +1,98 @@
-mod client;
-mod cmds;
-mod coordinator;
-mod guid;
-mod net;
-mod server;
-mod settings;
-mod types;
-
-use crate::types::Result;
-
 use clap::Parser;
-use client::ClientMap;
-use cmds::{Cli, Command};
-use coordinator::Coordinator;
-
-use server::Server;
-use settings::{Settings, SyncSettings};
+use smoo::{
+    client::{ClientMap, DisconnectedClients},
+    cmds::{Cli, CliCommand, Command},
+    coordinator::Coordinator,
+    net::udp_conn::UdpStats,
+    server::Server,
+    settings::{read_settings, save_settings, SyncSettings, SETTINGS_PATH},
+    shine::{load_shines, ShineBag},
+    tag::TagGame,
+    types::{Result, SMOError},
+};
 use std::{
-    collections::{HashMap, HashSet},
-    fs::File,
-    io::{BufReader, BufWriter, Write},
+    collections::HashMap,
+    io::Write,
     net::SocketAddr,
     sync::Arc,
+    time::{Duration, SystemTime},
 };
-use tokio::{
-    io::AsyncWriteExt,
-    join,
-    sync::{mpsc, RwLock},
-};
+use tokio::sync::{broadcast, mpsc, oneshot, RwLock};
 use tracing_subscriber::EnvFilter;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     tracing::info!("Starting server");
-    let (_to_coord, server, coordinator) = create_default_server();
+    let (to_coord, server, coordinator) = create_default_server();
     let settings = server.settings.read().await;
     let bind_addr = SocketAddr::new(settings.server.address, settings.server.port);
     tracing::info!("Binding tcp port to {}", bind_addr);
 
+    if settings.server.watch_settings {
+        tokio::task::spawn(watch_settings(to_coord.clone(), server.settings.clone()));
+    }
+
+    #[cfg(feature = "discord")]
+    if let Some(bot) = smoo::discord::DiscordBot::new(&settings.discord, to_coord.clone()) {
+        tokio::task::spawn(bot.run(coordinator.events.subscribe()));
+    }
+
     drop(settings);
     let serv_task = tokio::task::spawn(server.listen_for_clients(bind_addr));
     let coord_task = tokio::task::spawn(coordinator.handle_commands());
-    // let parser_task = tokio::task::spawn(parse_commands(to_coord));
+    let parser_task = tokio::task::spawn(parse_commands(to_coord));
 
     tracing::info!("Server ready");
-    // let _results = tokio::join!(serv_task, coord_task, parser_task);
-    let _results = tokio::join!(serv_task, coord_task);
-    Ok(())
-}
-
-fn read_settings() -> Result<Settings> {
-    let file = File::open("./settings.json")?;
-    let reader = BufReader::new(file);
-    let settings = serde_json::from_reader(reader)?;
-
-    Ok(settings)
-}
-
-fn save_settings(settings: &Settings) -> Result<()> {
-    tracing::debug!("Saving settings");
-    let file = File::create("./settings.json")?;
-    let writer = BufWriter::new(file);
-    serde_json::to_writer_pretty(writer, settings)?;
+    let _results = tokio::join!(serv_task, coord_task, parser_task);
     Ok(())
 }
 
 fn create_default_server() -> (mpsc::Sender<Command>, Server, Coordinator) {
-    // TODO Remove tihs debug panic option
-    let default_panic = std::panic::take_hook();
-    std::panic::set_hook(Box::new(move |info| {
-        default_panic(info);
-        std::process::exit(1);
-    }));
-
     tracing_subscriber::fmt()
         .with_env_filter(EnvFilter::from_default_env())
         .init();
 
     let (to_coord, from_clients) = mpsc::channel(100);
+    let (events, _) = broadcast::channel(32);
 
     let settings = read_settings().unwrap_or_default();
     save_settings(&settings).expect("Failed to save config");
 
+    let shine_bag = if settings.persist_shines.enabled {
+        let filename = &settings.persist_shines.filename;
+        match load_shines(filename) {
+            Ok(shines) => {
+                tracing::info!("Loaded {} shines from {}", shines.len(), filename);
+                shines
+            }
+            Err(e) => {
+                tracing::warn!("Failed to load shines from {}: {}", filename, e);
+                ShineBag::default()
+            }
+        }
+    } else {
+        ShineBag::default()
+    };
+
     let settings = Arc::new(RwLock::new(settings));
+    let udp_stats = Arc::new(UdpStats::default());
 
     let server = Server {
         settings: settings.clone(),
         to_coord: to_coord.clone(),
         udp_port: 51888,
+        udp_stats: udp_stats.clone(),
     };
     let coordinator = Coordinator {
-        shine_bag: Arc::new(RwLock::new(HashSet::default())),
+        shine_bag: Arc::new(RwLock::new(shine_bag)),
         from_clients,
         settings,
         clients: ClientMap::new(),
+        disconnected_clients: DisconnectedClients::default(),
         to_clients: HashMap::new(),
+        tag_game: TagGame::default(),
+        events,
+        udp_stats,
     };
     (to_coord, server, coordinator)
 }
@@ -103,28 +101,88 @@ async fn parse_commands(mut to_coord: mpsc::Sender<Command>) -> Result<()> {
     loop {
         let command_result = parse_command(&mut to_coord).await;
 
-        if let Err(e) = command_result {
-            println!("{}", e)
+        match command_result {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => println!("{}", e),
         }
     }
+
+    tracing::info!("Console input closed");
+    Ok(())
+}
+
+/// Reads and executes a single console command, returning false once stdin is closed
+async fn parse_command(to_coord: &mut mpsc::Sender<Command>) -> Result<bool> {
+    let input = match tokio::task::spawn_blocking(read_command).await?? {
+        Some(input) => input,
+        None => return Ok(false),
+    };
+
+    if input.trim().is_empty() {
+        return Ok(true);
+    }
+
+    let command = match Cli::try_parse_from(input.split_whitespace()) {
+        Ok(cli) => cli.cmd,
+        Err(e) => {
+            e.print()?;
+            return Ok(true);
+        }
+    };
+
+    let (reply, response) = oneshot::channel();
+    to_coord.send(Command::Cli(command, reply)).await?;
+    let response = response.await.map_err(|_| SMOError::RecvChannel)??;
+    println!("{}", response);
+    Ok(true)
 }
 
-async fn parse_command(to_coord: &mut mpsc::Sender<Command>) -> Result<()> {
-    let task = tokio::task::spawn_blocking(|| async { read_command() });
-    let command: Cli = join!(task).0?.await?;
+/// Polls settings.json and asks the coordinator to reload it after every change.
+/// Writes matching the running settings, like the server's own saves, are skipped.
+async fn watch_settings(to_coord: mpsc::Sender<Command>, settings: SyncSettings) -> Result<()> {
+    const POLL_INTERVAL: Duration = Duration::from_secs(2);
 
-    Ok(to_coord.send(Command::Cli(command.cmd)).await?)
+    let modified_time = || -> Option<SystemTime> {
+        let metadata = std::fs::metadata(SETTINGS_PATH).ok()?;
+        metadata.modified().ok()
+    };
+
+    tracing::info!("Watching {} for changes", SETTINGS_PATH);
+    let mut last_modified = modified_time();
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        let modified = modified_time();
+        if modified == last_modified {
+            continue;
+        }
+        last_modified = modified;
+
+        if let Ok(new_settings) = read_settings() {
+            if new_settings == *settings.read().await {
+                continue;
+            }
+        }
+
+        let (reply, response) = oneshot::channel();
+        to_coord
+            .send(Command::Cli(CliCommand::LoadSettings, reply))
+            .await?;
+        match response.await.map_err(|_| SMOError::RecvChannel)? {
+            Ok(result) => tracing::info!("{}", result),
+            Err(e) => tracing::warn!("Failed to reload settings: {}", e),
+        }
+    }
 }
 
-fn read_command() -> Result<Cli> {
-    let mut input = "> ".to_string();
+fn read_command() -> Result<Option<String>> {
+    let mut input = String::new();
 
-    print!("{}", input);
+    print!("> ");
     std::io::stdout().flush()?;
-    std::io::stdin().read_line(&mut input)?;
-    let input = input.trim().split(' ');
-    let cli = Cli::try_parse_from(input)?;
-    Ok(cli)
+    let read_amount = std::io::stdin().read_line(&mut input)?;
+    Ok((read_amount != 0).then_some(input))
 }
 
 #[cfg(test)]
@@ -132,7 +190,7 @@ mod test {
 
     use std::{net::SocketAddr, time::Duration};
 
-    use crate::{
+    use smoo::{
         cmds::ServerCommand,
         net::{connection::Connection, Packet},
         types::EncodingError,
@@ -161,7 +219,7 @@ mod test {
         let socket = tokio::net::TcpSocket::new_v4()?;
         tracing::debug!("Connecting to server");
         let conn = socket.connect(addr).await?;
-        let mut conn = Connection::new(conn);
+        let mut conn = Connection::new(conn)?;
         tracing::debug!("Connected to server");
 
         tracing::debug!("Reading data from server");
@@ -1,12 +1,12 @@
-use std::{io::Cursor, net::SocketAddr};
+use std::net::SocketAddr;
 
-use bytes::{Buf, BytesMut};
+use bytes::BytesMut;
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt, BufWriter},
     net::TcpStream,
 };
 
-use super::{encoding::Decodable, Packet, MAX_PACKET_SIZE};
+use super::{Packet, RawPacket, MAX_PACKET_SIZE};
 use crate::{
     net::encoding::Encodable,
     types::{EncodingError, Result},
@@ -20,42 +20,40 @@ pub struct Connection {
 }
 
 impl Connection {
-    pub fn new(stream: TcpStream) -> Self {
-        Connection {
-            addr: stream.peer_addr().unwrap(),
+    /// Fails if the peer already went away, as its address can't be read then
+    pub fn new(stream: TcpStream) -> Result<Self> {
+        Ok(Connection {
+            addr: stream.peer_addr()?,
             socket: BufWriter::new(stream),
             buff: BytesMut::with_capacity(1024),
-        }
+        })
     }
 
-    pub fn parse_packet(&mut self) -> Result<Option<Packet>> {
-        let mut buf = Cursor::new(&self.buff[..]);
-        match Packet::check(&mut buf) {
-            Ok(_) => {
-                let len = buf.position() as usize;
-
-                buf.set_position(0);
-
-                let packet = Packet::decode(&mut buf)?;
-                self.buff.advance(len);
+    pub fn parse_frame(&mut self) -> Result<Option<RawPacket>> {
+        Ok(RawPacket::split_from(&mut self.buff)?)
+    }
 
-                Ok(Some(packet))
-            }
-            Err(EncodingError::NotEnoughData) => Ok(None),
-            Err(e) => Err(e.into()),
+    pub fn parse_packet(&mut self) -> Result<Option<Packet>> {
+        match self.parse_frame()? {
+            Some(frame) => Ok(Some(frame.decode()?)),
+            None => Ok(None),
         }
     }
 
-    pub async fn read_packet(&mut self) -> Result<Packet> {
+    pub async fn read_frame(&mut self) -> Result<RawPacket> {
         loop {
-            if let Some(packet) = self.parse_packet()? {
-                return Ok(packet);
+            if let Some(frame) = self.parse_frame()? {
+                return Ok(frame);
             }
 
             self.read_socket().await?
         }
     }
 
+    pub async fn read_packet(&mut self) -> Result<Packet> {
+        Ok(self.read_frame().await?.decode()?)
+    }
+
     pub async fn read_socket(&mut self) -> Result<()> {
         let read_amount = self.socket.read_buf(&mut self.buff).await?;
         if read_amount == 0 {
@@ -82,4 +80,10 @@ impl Connection {
         tracing::trace!("Packet written");
         Ok(())
     }
+
+    pub async fn write_frame(&mut self, frame: &RawPacket) -> Result<()> {
+        self.socket.write_all(&frame.frame).await?;
+        self.socket.flush().await?;
+        Ok(())
+    }
 }
@@ -1,145 +1,147 @@
 use crate::types::EncodingError;
-use bytes::{Buf, Bytes};
-use serde::Deserializer;
-
-struct SMODeserializer {
-    input: Bytes,
+use serde::{
+    de::{DeserializeSeed, EnumAccess, SeqAccess, VariantAccess, Visitor},
+    Deserialize, Deserializer,
+};
+
+/// Deserializes values from the game's wire format, see
+/// [`SMOSerializer`](super::serializer::SMOSerializer) for the layout.
+///
+/// Since nothing is length prefixed, unsized strings, byte buffers and
+/// sequences take the rest of the input and an `Option` is `None` once the
+/// input is used up.
+pub struct SMODeserializer<'de> {
+    input: &'de [u8],
 }
 
-impl<'de> Deserializer<'de> for &'de mut SMODeserializer {
-    type Error = EncodingError;
-
-    fn deserialize_any<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
-    where
-        V: serde::de::Visitor<'de>,
-    {
-        Err(EncodingError::CustomError)
+impl<'de> SMODeserializer<'de> {
+    pub fn new(input: &'de [u8]) -> Self {
+        Self { input }
     }
 
-    fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value, Self::Error>
-    where
-        V: serde::de::Visitor<'de>,
-    {
-        visitor.visit_bool(self.input.get_u8() != 0)
+    /// Bytes that haven't been deserialized yet
+    pub fn remaining(&self) -> &'de [u8] {
+        self.input
     }
 
-    fn deserialize_i8<V>(self, visitor: V) -> Result<V::Value, Self::Error>
-    where
-        V: serde::de::Visitor<'de>,
-    {
-        visitor.visit_i8(self.input.get_i8())
-    }
-
-    fn deserialize_i16<V>(self, visitor: V) -> Result<V::Value, Self::Error>
-    where
-        V: serde::de::Visitor<'de>,
-    {
-        visitor.visit_i16(self.input.get_i16())
+    fn take(&mut self, len: usize) -> Result<&'de [u8], EncodingError> {
+        if self.input.len() < len {
+            return Err(EncodingError::NotEnoughData);
+        }
+        let (taken, rest) = self.input.split_at(len);
+        self.input = rest;
+        Ok(taken)
     }
 
-    fn deserialize_i32<V>(self, visitor: V) -> Result<V::Value, Self::Error>
-    where
-        V: serde::de::Visitor<'de>,
-    {
-        visitor.visit_i32(self.input.get_i32())
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N], EncodingError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
     }
 
-    fn deserialize_i64<V>(self, visitor: V) -> Result<V::Value, Self::Error>
-    where
-        V: serde::de::Visitor<'de>,
-    {
-        visitor.visit_i64(self.input.get_i64())
+    fn take_rest(&mut self) -> &'de [u8] {
+        std::mem::take(&mut self.input)
     }
+}
 
-    fn deserialize_u8<V>(self, visitor: V) -> Result<V::Value, Self::Error>
-    where
-        V: serde::de::Visitor<'de>,
-    {
-        visitor.visit_u8(self.input.get_u8())
-    }
+/// Deserializes a value from the start of `input`, ignoring any trailing bytes
+pub fn from_bytes<'de, T>(input: &'de [u8]) -> Result<T, EncodingError>
+where
+    T: Deserialize<'de>,
+{
+    T::deserialize(&mut SMODeserializer::new(input))
+}
 
-    fn deserialize_u16<V>(self, visitor: V) -> Result<V::Value, Self::Error>
-    where
-        V: serde::de::Visitor<'de>,
-    {
-        visitor.visit_u16(self.input.get_u16())
-    }
+macro_rules! deserialize_num {
+    ($deserialize:ident, $visit:ident, $ty:ty) => {
+        fn $deserialize<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+        where
+            V: Visitor<'de>,
+        {
+            visitor.$visit(<$ty>::from_le_bytes(self.take_array()?))
+        }
+    };
+}
 
-    fn deserialize_u32<V>(self, visitor: V) -> Result<V::Value, Self::Error>
-    where
-        V: serde::de::Visitor<'de>,
-    {
-        visitor.visit_u32(self.input.get_u32())
-    }
+impl<'de> Deserializer<'de> for &mut SMODeserializer<'de> {
+    type Error = EncodingError;
 
-    fn deserialize_u64<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    fn deserialize_any<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
     where
-        V: serde::de::Visitor<'de>,
+        V: Visitor<'de>,
     {
-        visitor.visit_u64(self.input.get_u64())
+        Err(EncodingError::CustomError)
     }
 
-    fn deserialize_f32<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
-        V: serde::de::Visitor<'de>,
+        V: Visitor<'de>,
     {
-        visitor.visit_f32(self.input.get_f32())
+        visitor.visit_bool(self.take(1)?[0] != 0)
     }
 
-    fn deserialize_f64<V>(self, visitor: V) -> Result<V::Value, Self::Error>
-    where
-        V: serde::de::Visitor<'de>,
-    {
-        visitor.visit_f64(self.input.get_f64())
-    }
+    deserialize_num!(deserialize_i8, visit_i8, i8);
+    deserialize_num!(deserialize_i16, visit_i16, i16);
+    deserialize_num!(deserialize_i32, visit_i32, i32);
+    deserialize_num!(deserialize_i64, visit_i64, i64);
+    deserialize_num!(deserialize_u8, visit_u8, u8);
+    deserialize_num!(deserialize_u16, visit_u16, u16);
+    deserialize_num!(deserialize_u32, visit_u32, u32);
+    deserialize_num!(deserialize_u64, visit_u64, u64);
+    deserialize_num!(deserialize_f32, visit_f32, f32);
+    deserialize_num!(deserialize_f64, visit_f64, f64);
 
     fn deserialize_char<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
-        V: serde::de::Visitor<'de>,
+        V: Visitor<'de>,
     {
-        let c: char =
-            char::from_u32(self.input.get_u8().into()).ok_or(EncodingError::CustomError)?;
-        visitor.visit_char(c)
+        let c = u32::from_le_bytes(self.take_array()?);
+        visitor.visit_char(char::from_u32(c).ok_or(EncodingError::CustomError)?)
     }
 
-    fn deserialize_str<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+    fn deserialize_str<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
-        V: serde::de::Visitor<'de>,
+        V: Visitor<'de>,
     {
-        unimplemented!()
+        let s = std::str::from_utf8(self.take_rest())?;
+        visitor.visit_borrowed_str(s.trim_end_matches(char::from(0)))
     }
 
-    fn deserialize_string<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+    fn deserialize_string<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
-        V: serde::de::Visitor<'de>,
+        V: Visitor<'de>,
     {
-        unimplemented!()
+        self.deserialize_str(visitor)
     }
 
-    fn deserialize_bytes<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
-        V: serde::de::Visitor<'de>,
+        V: Visitor<'de>,
     {
-        unimplemented!()
+        visitor.visit_borrowed_bytes(self.take_rest())
     }
 
-    fn deserialize_byte_buf<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
-        V: serde::de::Visitor<'de>,
+        V: Visitor<'de>,
     {
-        unimplemented!()
+        self.deserialize_bytes(visitor)
     }
 
-    fn deserialize_option<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
-        V: serde::de::Visitor<'de>,
+        V: Visitor<'de>,
     {
-        unimplemented!()
+        if self.input.is_empty() {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
     }
 
     fn deserialize_unit<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
-        V: serde::de::Visitor<'de>,
+        V: Visitor<'de>,
     {
         visitor.visit_unit()
     }
@@ -150,7 +152,7 @@ impl<'de> Deserializer<'de> for &'de mut SMODeserializer {
         visitor: V,
     ) -> Result<V::Value, Self::Error>
     where
-        V: serde::de::Visitor<'de>,
+        V: Visitor<'de>,
     {
         visitor.visit_unit()
     }
@@ -161,85 +163,93 @@ impl<'de> Deserializer<'de> for &'de mut SMODeserializer {
         visitor: V,
     ) -> Result<V::Value, Self::Error>
     where
-        V: serde::de::Visitor<'de>,
+        V: Visitor<'de>,
     {
         visitor.visit_newtype_struct(self)
     }
 
-    fn deserialize_seq<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
-        V: serde::de::Visitor<'de>,
+        V: Visitor<'de>,
     {
-        unimplemented!()
+        visitor.visit_seq(Elements {
+            de: self,
+            remaining: None,
+        })
     }
 
-    fn deserialize_tuple<V>(self, _len: usize, _visitor: V) -> Result<V::Value, Self::Error>
+    fn deserialize_tuple<V>(self, len: usize, visitor: V) -> Result<V::Value, Self::Error>
     where
-        V: serde::de::Visitor<'de>,
+        V: Visitor<'de>,
     {
-        unimplemented!()
+        visitor.visit_seq(Elements {
+            de: self,
+            remaining: Some(len),
+        })
     }
 
     fn deserialize_tuple_struct<V>(
         self,
         _name: &'static str,
-        _len: usize,
-        _visitor: V,
+        len: usize,
+        visitor: V,
     ) -> Result<V::Value, Self::Error>
     where
-        V: serde::de::Visitor<'de>,
+        V: Visitor<'de>,
     {
-        unimplemented!()
+        self.deserialize_tuple(len, visitor)
     }
 
     fn deserialize_map<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
     where
-        V: serde::de::Visitor<'de>,
+        V: Visitor<'de>,
     {
-        unimplemented!()
+        Err(EncodingError::CustomError)
     }
 
     fn deserialize_struct<V>(
         self,
         _name: &'static str,
-        _fields: &'static [&'static str],
-        _visitor: V,
+        fields: &'static [&'static str],
+        visitor: V,
     ) -> Result<V::Value, Self::Error>
     where
-        V: serde::de::Visitor<'de>,
+        V: Visitor<'de>,
     {
-        todo!()
+        self.deserialize_tuple(fields.len(), visitor)
     }
 
     fn deserialize_enum<V>(
         self,
         _name: &'static str,
         _variants: &'static [&'static str],
-        _visitor: V,
+        visitor: V,
     ) -> Result<V::Value, Self::Error>
     where
-        V: serde::de::Visitor<'de>,
+        V: Visitor<'de>,
     {
-        todo!()
+        visitor.visit_enum(self)
     }
 
-    fn deserialize_identifier<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+    fn deserialize_identifier<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
-        V: serde::de::Visitor<'de>,
+        V: Visitor<'de>,
     {
-        todo!()
+        // Variants are written as their u16 index
+        let index = u16::from_le_bytes(self.take_array()?);
+        visitor.visit_u32(index.into())
     }
 
     fn deserialize_ignored_any<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
     where
-        V: serde::de::Visitor<'de>,
+        V: Visitor<'de>,
     {
-        todo!()
+        Err(EncodingError::CustomError)
     }
 
     fn deserialize_i128<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
-        V: serde::de::Visitor<'de>,
+        V: Visitor<'de>,
     {
         let _ = visitor;
         Err(serde::de::Error::custom("i128 is not supported"))
@@ -247,7 +257,7 @@ impl<'de> Deserializer<'de> for &'de mut SMODeserializer {
 
     fn deserialize_u128<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
-        V: serde::de::Visitor<'de>,
+        V: Visitor<'de>,
     {
         let _ = visitor;
         Err(serde::de::Error::custom("u128 is not supported"))
@@ -257,3 +267,76 @@ impl<'de> Deserializer<'de> for &'de mut SMODeserializer {
         false
     }
 }
+
+/// Elements of a fixed length tuple, or of a sequence running to the end of the input
+struct Elements<'a, 'de> {
+    de: &'a mut SMODeserializer<'de>,
+    remaining: Option<usize>,
+}
+
+impl<'de, 'a> SeqAccess<'de> for Elements<'a, 'de> {
+    type Error = EncodingError;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, Self::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        match &mut self.remaining {
+            Some(0) => return Ok(None),
+            Some(remaining) => *remaining -= 1,
+            None if self.de.input.is_empty() => return Ok(None),
+            None => {}
+        }
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.remaining
+    }
+}
+
+impl<'de> EnumAccess<'de> for &mut SMODeserializer<'de> {
+    type Error = EncodingError;
+    type Variant = Self;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self::Variant), Self::Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let value = seed.deserialize(&mut *self)?;
+        Ok((value, self))
+    }
+}
+
+impl<'de> VariantAccess<'de> for &mut SMODeserializer<'de> {
+    type Error = EncodingError;
+
+    fn unit_variant(self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, Self::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V>(self, len: usize, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn struct_variant<V>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_tuple(fields.len(), visitor)
+    }
+}
@@ -0,0 +1,79 @@
+//! Fixed-width, null padded strings for the SMO serde format.
+//!
+//! ```
+//! use serde::{Deserialize, Serialize};
+//! use smoo::net::fixed_str;
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Capture {
+//!     #[serde(
+//!         serialize_with = "fixed_str::serialize::<_, 0x20>",
+//!         deserialize_with = "fixed_str::deserialize::<_, 0x20>"
+//!     )]
+//!     model: String,
+//! }
+//! ```
+
+use std::fmt;
+
+use serde::{
+    de::{Error as _, SeqAccess, Visitor},
+    ser::{Error as _, SerializeTuple},
+    Deserializer, Serializer,
+};
+
+/// Writes `s` as exactly `N` bytes, padding with nulls. Strings longer than
+/// `N` bytes are an error rather than being cut off mid character.
+pub fn serialize<S, const N: usize>(s: &str, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    if s.len() > N {
+        return Err(S::Error::custom(format!(
+            "string of {} bytes doesn't fit in {}",
+            s.len(),
+            N
+        )));
+    }
+
+    let mut tuple = serializer.serialize_tuple(N)?;
+    for byte in s.bytes().chain(std::iter::repeat(0)).take(N) {
+        tuple.serialize_element(&byte)?;
+    }
+    tuple.end()
+}
+
+/// Reads `N` bytes and strips the null padding
+pub fn deserialize<'de, D, const N: usize>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_tuple(N, FixedStrVisitor::<N>)
+}
+
+struct FixedStrVisitor<const N: usize>;
+
+impl<'de, const N: usize> Visitor<'de> for FixedStrVisitor<N> {
+    type Value = String;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} bytes of null padded utf8", N)
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut bytes = Vec::with_capacity(N);
+        for i in 0..N {
+            let byte: u8 = seq
+                .next_element()?
+                .ok_or_else(|| A::Error::invalid_length(i, &self))?;
+            bytes.push(byte);
+        }
+
+        let end = bytes.iter().position(|b| *b == 0).unwrap_or(N);
+        bytes.truncate(end);
+        String::from_utf8(bytes).map_err(A::Error::custom)
+    }
+}
@@ -1,27 +1,150 @@
-use serde::{Deserialize, Serialize};
+use bytes::{Buf, BufMut};
 
-use crate::guid::Guid;
+use super::{
+    encoding::{Decodable, Encodable},
+    MAX_PACKET_SIZE,
+};
+use crate::{guid::Guid, types::EncodingError};
 
-#[derive(Serialize, Deserialize)]
+/// Size of the id, type and data size fields in front of every packet
+pub const HEADER_SIZE: usize = 16 + 2 + 2;
+/// Largest data size accepted, anything above can't be a real packet
+pub const MAX_DATA_SIZE: usize = MAX_PACKET_SIZE - HEADER_SIZE;
+
+/// The fixed part of a packet, which can be read without decoding the body
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct PacketHeader {
     pub id: Guid,
-    pub p_type: PacketType,
+    /// Raw type tag, kept as is so packets of unknown types can be relayed
+    pub tag: u16,
     pub data_size: u16,
 }
 
-#[derive(Serialize, Deserialize)]
+impl PacketHeader {
+    pub fn packet_type(&self) -> Option<PacketType> {
+        PacketType::try_from(self.tag).ok()
+    }
+}
+
+#[repr(u16)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum PacketType {
-    Unknown,
-    Init,
-    Player,
-    Cap,
-    Game,
-    Tag,
-    Connect,
-    Disconnect,
-    Costume,
-    Shine,
-    Capture,
-    ChangeStage,
-    Command,
+    Unknown = 0,
+    Init = 1,
+    Player = 2,
+    Cap = 3,
+    Game = 4,
+    Tag = 5,
+    Connect = 6,
+    Disconnect = 7,
+    Costume = 8,
+    Shine = 9,
+    Capture = 10,
+    ChangeStage = 11,
+    Command = 12,
+    UdpInit = 13,
+    HolePunch = 14,
+    /// Extension packets, only sent to clients that negotiated them
+    ServerMessage = 0x100,
+    Ping = 0x101,
+}
+
+impl TryFrom<u16> for PacketType {
+    type Error = EncodingError;
+
+    fn try_from(tag: u16) -> Result<Self, Self::Error> {
+        let p_type = match tag {
+            0 => Self::Unknown,
+            1 => Self::Init,
+            2 => Self::Player,
+            3 => Self::Cap,
+            4 => Self::Game,
+            5 => Self::Tag,
+            6 => Self::Connect,
+            7 => Self::Disconnect,
+            8 => Self::Costume,
+            9 => Self::Shine,
+            10 => Self::Capture,
+            11 => Self::ChangeStage,
+            12 => Self::Command,
+            13 => Self::UdpInit,
+            14 => Self::HolePunch,
+            0x100 => Self::ServerMessage,
+            0x101 => Self::Ping,
+            _ => return Err(EncodingError::UnknownPacketType(tag)),
+        };
+        Ok(p_type)
+    }
+}
+
+impl From<PacketType> for u16 {
+    fn from(p_type: PacketType) -> Self {
+        p_type as u16
+    }
+}
+
+impl<R> Decodable<R> for PacketHeader
+where
+    R: Buf,
+{
+    fn decode(buf: &mut R) -> Result<Self, EncodingError> {
+        if buf.remaining() < HEADER_SIZE {
+            return Err(EncodingError::NotEnoughData);
+        }
+
+        let mut id = [0; 16];
+        buf.copy_to_slice(&mut id);
+        let tag = buf.get_u16_le();
+        let data_size = buf.get_u16_le();
+        if usize::from(data_size) > MAX_DATA_SIZE {
+            return Err(EncodingError::PacketTooLarge(data_size));
+        }
+
+        Ok(PacketHeader {
+            id: id.into(),
+            tag,
+            data_size,
+        })
+    }
+}
+
+impl<W> Encodable<W> for PacketHeader
+where
+    W: BufMut,
+{
+    fn encode(&self, buf: &mut W) -> Result<(), EncodingError> {
+        buf.put_slice(&self.id.id[..]);
+        buf.put_u16_le(self.tag);
+        buf.put_u16_le(self.data_size);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn packet_type_tags() {
+        for tag in 0..=14 {
+            let p_type = PacketType::try_from(tag).unwrap();
+            assert_eq!(u16::from(p_type), tag);
+        }
+        assert!(matches!(
+            PacketType::try_from(15),
+            Err(EncodingError::UnknownPacketType(15))
+        ));
+        assert_eq!(PacketType::try_from(0x101).unwrap(), PacketType::Ping);
+    }
+
+    #[test]
+    fn rejects_oversized_data() {
+        let mut buf = vec![0; 16];
+        buf.extend_from_slice(&2u16.to_le_bytes());
+        buf.extend_from_slice(&u16::MAX.to_le_bytes());
+        assert!(matches!(
+            PacketHeader::decode(&mut &buf[..]),
+            Err(EncodingError::PacketTooLarge(u16::MAX))
+        ));
+    }
 }
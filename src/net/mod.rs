@@ -1,6 +1,15 @@
 pub mod connection;
+pub mod deserializer;
 pub mod encoding;
+pub mod fixed_str;
+pub mod header;
 mod packet;
+pub mod protocol;
+pub mod serializer;
 pub mod udp_conn;
+pub mod udp_mux;
 
+pub use deserializer::{from_bytes, SMODeserializer};
+pub use header::{PacketHeader, PacketType, HEADER_SIZE, MAX_DATA_SIZE};
 pub use packet::*;
+pub use serializer::{to_buf, to_bytes, SMOSerializer};
@@ -1,11 +1,15 @@
 use std::{fmt::Debug, io::Cursor};
 
-use super::encoding::{Decodable, Encodable};
+use super::{
+    encoding::{Decodable, Encodable},
+    header::{PacketHeader, PacketType, HEADER_SIZE, MAX_DATA_SIZE},
+    protocol::{Extensions, ProtocolInfo, PROTOCOL_INFO_SIZE},
+};
 use crate::{
     guid::Guid,
     types::{Costume, EncodingError, Quaternion, Vector3},
 };
-use bytes::{Buf, BufMut, BytesMut};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use quickcheck::Arbitrary;
 
 type Result<T> = std::result::Result<T, EncodingError>;
@@ -18,6 +22,7 @@ const STAGE_GAME_NAME_SIZE: usize = 0x40;
 const STAGE_CHANGE_NAME_SIZE: usize = 0x30;
 const STAGE_ID_SIZE: usize = 0x10;
 const CLIENT_NAME_SIZE: usize = COSTUME_NAME_SIZE;
+pub const SERVER_MESSAGE_SIZE: usize = 0x80;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Packet {
@@ -42,21 +47,22 @@ impl Packet {
         self.data_size = self.data.get_size() as u16;
     }
 
-    pub fn check(buf: &mut Cursor<&[u8]>) -> Result<u64> {
-        let header_size = 16 + 2;
-        let start_pos = buf.position();
-        if buf.remaining() < header_size + 2 {
-            // tracing::debug!(
-            //     "Not enough bytes for header: {} < {}",
-            //     buf.remaining(),
-            //     header_size + 2
-            // );
-            return Err(EncodingError::NotEnoughData);
+    /// The header the packet is encoded with. A data size smaller than the
+    /// data needs is raised to fit it, a larger one is filled with padding.
+    pub fn header(&self) -> PacketHeader {
+        let data_size = usize::from(self.data_size).max(self.data.get_size());
+        PacketHeader {
+            id: self.id,
+            tag: self.data.get_type_id(),
+            data_size: data_size as u16,
         }
+    }
 
-        buf.advance(header_size);
-        let size = buf.get_u16_le().into();
-        // tracing::info!("Size: {}", size);
+    /// Checks that a whole packet is buffered, returning its length
+    pub fn check(buf: &mut Cursor<&[u8]>) -> Result<u64> {
+        let start_pos = buf.position();
+        let header = PacketHeader::decode(buf)?;
+        let size = header.data_size.into();
         if buf.remaining() < size {
             tracing::trace!("Not enough bytes for data: {} < {}", buf.remaining(), size);
             return Err(EncodingError::NotEnoughData);
@@ -66,6 +72,61 @@ impl Packet {
     }
 }
 
+/// A packet whose body is kept as the bytes it was received as, so it can be
+/// relayed to every other client without decoding or re-encoding it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawPacket {
+    pub header: PacketHeader,
+    /// Header and body exactly as received
+    pub frame: Bytes,
+}
+
+impl RawPacket {
+    /// Splits the first packet off `buf`, or returns `None` until all of it is buffered
+    pub fn split_from(buf: &mut BytesMut) -> Result<Option<RawPacket>> {
+        let header = match PacketHeader::decode(&mut &buf[..]) {
+            Ok(header) => header,
+            Err(EncodingError::NotEnoughData) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let len = HEADER_SIZE + usize::from(header.data_size);
+        if buf.len() < len {
+            return Ok(None);
+        }
+        let frame = buf.split_to(len).freeze();
+        Ok(Some(RawPacket { header, frame }))
+    }
+
+    pub fn from_packet(packet: &Packet) -> Result<RawPacket> {
+        let mut buf = BytesMut::with_capacity(HEADER_SIZE + usize::from(packet.data_size));
+        packet.encode(&mut buf)?;
+        Ok(RawPacket {
+            header: packet.header(),
+            frame: buf.freeze(),
+        })
+    }
+
+    pub fn decode(&self) -> Result<Packet> {
+        Packet::decode(&mut self.frame.clone())
+    }
+
+    /// Checks the frame is large enough for its type, since relayed frames
+    /// never go through decoding
+    pub fn check_size(&self) -> Result<()> {
+        check_data_size(&self.header)
+    }
+
+    /// Movement updates make up most of the traffic and are only relayed, so
+    /// they are the packets left undecoded
+    pub fn needs_decode(&self) -> bool {
+        !matches!(
+            self.header.packet_type(),
+            Some(PacketType::Player | PacketType::Cap)
+        )
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum PacketData {
     Unhandled {
@@ -74,6 +135,8 @@ pub enum PacketData {
     },
     Init {
         max_players: u16,
+        /// What the server agreed on, only sent to clients that asked
+        protocol: Option<ProtocolInfo>,
     },
     Player {
         pos: Vector3,
@@ -103,6 +166,8 @@ pub enum PacketData {
         c_type: ConnectionType,
         max_player: u16,
         client_name: String,
+        /// Advertised by clients that support protocol extensions
+        protocol: Option<ProtocolInfo>,
     },
     Disconnect,
     Costume(Costume),
@@ -124,49 +189,103 @@ pub enum PacketData {
         port: u16,
     },
     HolePunch,
+    ServerMessage {
+        message: String,
+    },
+    Ping {
+        nonce: u32,
+    },
+}
+
+/// Size of the data of a packet type, which decoding relies on being there
+fn payload_size(p_type: PacketType) -> usize {
+    match p_type {
+        PacketType::Unknown => 0,
+        PacketType::Init => 2,
+        PacketType::Player => 0x38,
+        PacketType::Cap => 29 + CAP_ANIM_SIZE,
+        PacketType::Game => 2 + STAGE_GAME_NAME_SIZE,
+        PacketType::Tag => 5,
+        PacketType::Connect => 6 + CLIENT_NAME_SIZE,
+        PacketType::Disconnect => 0,
+        PacketType::Costume => COSTUME_NAME_SIZE * 2,
+        PacketType::Shine => 5,
+        PacketType::Capture => COSTUME_NAME_SIZE,
+        PacketType::ChangeStage => STAGE_ID_SIZE + STAGE_CHANGE_NAME_SIZE + 2,
+        PacketType::Command => 0,
+        PacketType::UdpInit => 2,
+        PacketType::HolePunch => 0,
+        PacketType::ServerMessage => SERVER_MESSAGE_SIZE,
+        PacketType::Ping => 4,
+    }
+}
+
+/// Checks a packet declares at least as much data as its type needs
+fn check_data_size(header: &PacketHeader) -> Result<()> {
+    match header.packet_type() {
+        Some(p_type) if usize::from(header.data_size) < payload_size(p_type) => {
+            Err(EncodingError::PayloadTooShort {
+                tag: header.tag,
+                size: header.data_size,
+            })
+        }
+        _ => Ok(()),
+    }
 }
 
 impl PacketData {
     fn get_size(&self) -> usize {
         match self {
             Self::Unhandled { data, .. } => data.len(),
-            Self::Init { .. } => 2,
-            Self::Player { .. } => 0x38,
-            Self::Cap { .. } => 29 + CAP_ANIM_SIZE,
-            Self::Game { .. } => 2 + STAGE_GAME_NAME_SIZE,
-            Self::Tag { .. } => 5,
-            Self::Connect { .. } => 6 + CLIENT_NAME_SIZE,
-            Self::Disconnect { .. } => 0,
-            Self::Costume { .. } => COSTUME_NAME_SIZE * 2,
-            Self::Shine { .. } => 5,
-            Self::Capture { .. } => COSTUME_NAME_SIZE,
-            Self::ChangeStage { .. } => STAGE_ID_SIZE + STAGE_CHANGE_NAME_SIZE + 2,
-            Self::Command { .. } => 0,
-            Self::UdpInit { .. } => 2,
-            Self::HolePunch { .. } => 0,
+            _ => {
+                let protocol_size = match self.protocol() {
+                    Some(_) => PROTOCOL_INFO_SIZE,
+                    None => 0,
+                };
+                self.packet_type().map(payload_size).unwrap_or_default() + protocol_size
+            }
+        }
+    }
+
+    /// Protocol info piggy-backed on an `Init` or `Connect`
+    pub fn protocol(&self) -> Option<&ProtocolInfo> {
+        match self {
+            Self::Init { protocol, .. } | Self::Connect { protocol, .. } => protocol.as_ref(),
+            _ => None,
         }
     }
 
     fn get_type_id(&self) -> u16 {
         match self {
             Self::Unhandled { tag, .. } => *tag,
-            Self::Init { .. } => 1,
-            Self::Player { .. } => 2,
-            Self::Cap { .. } => 3,
-            Self::Game { .. } => 4,
-            Self::Tag { .. } => 5,
-            Self::Connect { .. } => 6,
-            Self::Disconnect { .. } => 7,
-            Self::Costume { .. } => 8,
-            Self::Shine { .. } => 9,
-            Self::Capture { .. } => 10,
-            Self::ChangeStage { .. } => 11,
-            Self::Command { .. } => 12,
-            Self::UdpInit { .. } => 13,
-            Self::HolePunch { .. } => 14,
+            _ => self.packet_type().map(u16::from).unwrap_or_default(),
         }
     }
 
+    /// The type of a handled packet, `None` for unhandled ones
+    pub fn packet_type(&self) -> Option<PacketType> {
+        let p_type = match self {
+            Self::Unhandled { .. } => return None,
+            Self::Init { .. } => PacketType::Init,
+            Self::Player { .. } => PacketType::Player,
+            Self::Cap { .. } => PacketType::Cap,
+            Self::Game { .. } => PacketType::Game,
+            Self::Tag { .. } => PacketType::Tag,
+            Self::Connect { .. } => PacketType::Connect,
+            Self::Disconnect { .. } => PacketType::Disconnect,
+            Self::Costume { .. } => PacketType::Costume,
+            Self::Shine { .. } => PacketType::Shine,
+            Self::Capture { .. } => PacketType::Capture,
+            Self::ChangeStage { .. } => PacketType::ChangeStage,
+            Self::Command { .. } => PacketType::Command,
+            Self::UdpInit { .. } => PacketType::UdpInit,
+            Self::HolePunch { .. } => PacketType::HolePunch,
+            Self::ServerMessage { .. } => PacketType::ServerMessage,
+            Self::Ping { .. } => PacketType::Ping,
+        };
+        Some(p_type)
+    }
+
     pub fn get_type_name(&self) -> String {
         match self {
             Self::Unhandled { .. } => "unhandled",
@@ -184,6 +303,8 @@ impl PacketData {
             Self::Command { .. } => "command",
             Self::UdpInit { .. } => "udpInit",
             Self::HolePunch { .. } => "holePunch",
+            Self::ServerMessage { .. } => "serverMessage",
+            Self::Ping { .. } => "ping",
         }
         .to_string()
     }
@@ -210,26 +331,25 @@ where
     R: Buf,
 {
     fn decode(buf: &mut R) -> std::result::Result<Self, EncodingError> {
-        if buf.remaining() < (16 + 2 + 2) {
-            tracing::trace!("Header size failed");
-            return Err(EncodingError::NotEnoughData);
-        }
-
-        let mut id = [0; 16];
-        buf.copy_to_slice(&mut id);
-        let p_type = buf.get_u16_le();
-        let p_size = buf.get_u16_le();
+        let header = PacketHeader::decode(buf)?;
+        let p_size = header.data_size;
 
         if buf.remaining() < p_size.into() {
             tracing::trace!("data size failed");
             return Err(EncodingError::NotEnoughData);
         }
 
-        let data = match p_type {
-            1 => PacketData::Init {
+        check_data_size(&header)?;
+        // Only the declared data is read, whatever a field doesn't use is padding
+        let mut data = buf.copy_to_bytes(p_size.into());
+        let buf = &mut data;
+
+        let data = match header.packet_type() {
+            Some(PacketType::Init) => PacketData::Init {
                 max_players: buf.get_u16_le(),
+                protocol: ProtocolInfo::decode_trailing(buf),
             },
-            2 => PacketData::Player {
+            Some(PacketType::Player) => PacketData::Player {
                 // pos: Vector3::new(buf.get_f32_le(), buf.get_f32_le(), buf.get_f32_le()),
                 pos: Vector3::decode(buf)?,
                 rot: Quaternion::decode(buf)?,
@@ -243,22 +363,18 @@ where
                 act: buf.get_u16_le(),
                 sub_act: buf.get_u16_le(),
             },
-            3 => {
-                let packet = PacketData::Cap {
-                    pos: Vector3::decode(buf)?,
-                    rot: Quaternion::decode(buf)?,
-                    cap_out: buf.get_u8() != 0,
-                    cap_anim: std::str::from_utf8(&buf.copy_to_bytes(COSTUME_NAME_SIZE)[..])?
-                        .to_string(),
-                };
-                packet
-            }
-            4 => PacketData::Game {
+            Some(PacketType::Cap) => PacketData::Cap {
+                pos: Vector3::decode(buf)?,
+                rot: Quaternion::decode(buf)?,
+                cap_out: buf.get_u8() != 0,
+                cap_anim: buf_size_to_string(buf, CAP_ANIM_SIZE)?,
+            },
+            Some(PacketType::Game) => PacketData::Game {
                 is_2d: buf.get_u8() != 0,
                 scenario_num: buf.get_u8(),
                 stage: buf_size_to_string(buf, STAGE_GAME_NAME_SIZE)?,
             },
-            5 => PacketData::Tag {
+            Some(PacketType::Tag) => PacketData::Tag {
                 update_type: if buf.get_u8() == 1 {
                     TagUpdate::Time
                 } else {
@@ -268,7 +384,7 @@ where
                 seconds: buf.get_u8(),
                 minutes: buf.get_u16_le(),
             },
-            6 => {
+            Some(PacketType::Connect) => {
                 tracing::debug!("Parsing connect: {}", buf.remaining());
                 let c_type = if buf.get_u32_le() == 0 {
                     ConnectionType::FirstConnection
@@ -281,48 +397,54 @@ where
                     c_type,
                     max_player,
                     client_name,
+                    protocol: ProtocolInfo::decode_trailing(buf),
                 }
             }
-            7 => PacketData::Disconnect,
-            8 => PacketData::Costume(Costume {
+            Some(PacketType::Disconnect) => PacketData::Disconnect,
+            Some(PacketType::Costume) => PacketData::Costume(Costume {
                 body_name: buf_size_to_string(buf, COSTUME_NAME_SIZE)?,
                 cap_name: buf_size_to_string(buf, COSTUME_NAME_SIZE)?,
             }),
-            9 => PacketData::Shine {
+            Some(PacketType::Shine) => PacketData::Shine {
                 shine_id: buf.get_i32_le(),
                 is_grand: buf.get_u8() != 0,
             },
-            10 => PacketData::Capture {
+            Some(PacketType::Capture) => PacketData::Capture {
                 model: buf_size_to_string(buf, COSTUME_NAME_SIZE)?,
             },
-            11 => PacketData::ChangeStage {
+            Some(PacketType::ChangeStage) => PacketData::ChangeStage {
                 stage: buf_size_to_string(buf, STAGE_CHANGE_NAME_SIZE)?,
                 id: buf_size_to_string(buf, STAGE_ID_SIZE)?,
                 scenerio: buf.get_i8(),
                 sub_scenario: buf.get_u8(),
             },
-            12 => PacketData::Command {},
-            13 => PacketData::UdpInit {
+            Some(PacketType::Command) => PacketData::Command {},
+            Some(PacketType::UdpInit) => PacketData::UdpInit {
                 port: buf.get_u16_le(),
             },
-            _ => PacketData::Unhandled {
-                tag: p_type,
-                data: buf.copy_to_bytes(p_size.into())[..].to_vec(),
+            Some(PacketType::HolePunch) => PacketData::HolePunch,
+            Some(PacketType::ServerMessage) => PacketData::ServerMessage {
+                message: buf_size_to_string(buf, SERVER_MESSAGE_SIZE)?,
+            },
+            Some(PacketType::Ping) => PacketData::Ping {
+                nonce: buf.get_u32_le(),
+            },
+            Some(PacketType::Unknown) | None => PacketData::Unhandled {
+                tag: header.tag,
+                data: buf.to_vec(),
             },
         };
 
-        let excess_padding = p_size as usize - data.get_size();
-        if excess_padding > 0 {
+        if buf.has_remaining() {
             tracing::trace!(
-                "Removing extra padding from packet {}: {} bytes",
+                "Ignoring extra padding in packet {}: {} bytes",
                 data.get_type_name(),
-                excess_padding
+                buf.remaining()
             );
-            buf.advance(excess_padding);
         }
 
         Ok(Packet {
-            id: id.into(),
+            id: header.id,
             data_size: p_size,
             data,
         })
@@ -334,13 +456,18 @@ where
     W: BufMut,
 {
     fn encode(&self, buf: &mut W) -> Result<()> {
-        buf.put_slice(&self.id.id[..]);
-        buf.put_u16_le(self.data.get_type_id());
-        buf.put_u16_le(self.data_size);
+        let header = self.header();
+        header.encode(buf)?;
         match &self.data {
             PacketData::Unhandled { data, .. } => buf.put_slice(&data[..]),
-            PacketData::Init { max_players } => {
+            PacketData::Init {
+                max_players,
+                protocol,
+            } => {
                 buf.put_u16_le(*max_players);
+                if let Some(protocol) = protocol {
+                    protocol.encode(buf)?;
+                }
             }
             PacketData::Player {
                 pos,
@@ -396,6 +523,7 @@ where
                 c_type,
                 max_player,
                 client_name,
+                protocol,
             } => {
                 let tag = match c_type {
                     ConnectionType::FirstConnection => 0,
@@ -404,6 +532,9 @@ where
                 buf.put_u32_le(tag);
                 buf.put_u16_le(*max_player);
                 buf.put_slice(&str_to_sized_array::<CLIENT_NAME_SIZE>(client_name));
+                if let Some(protocol) = protocol {
+                    protocol.encode(buf)?;
+                }
             }
             PacketData::Disconnect => {}
             PacketData::Costume(Costume {
@@ -431,23 +562,17 @@ where
                 buf.put_i8(*scenerio);
                 buf.put_u8(*sub_scenario);
             }
-            PacketData::ChangeStage {
-                stage,
-                id,
-                scenerio,
-                sub_scenario,
-            } => {
-                buf.put_slice(&str_to_sized_array::<STAGE_CHANGE_NAME_SIZE>(stage));
-                buf.put_slice(&str_to_sized_array::<STAGE_ID_SIZE>(id));
-                buf.put_i8(*scenerio);
-                buf.put_u8(*sub_scenario);
-            }
             PacketData::Command => {}
             PacketData::UdpInit { port } => {
                 buf.put_u16_le(*port);
             }
             PacketData::HolePunch => {}
+            PacketData::ServerMessage { message } => {
+                buf.put_slice(&str_to_sized_array::<SERVER_MESSAGE_SIZE>(message));
+            }
+            PacketData::Ping { nonce } => buf.put_u32_le(*nonce),
         }
+        buf.put_bytes(0, usize::from(header.data_size) - self.data.get_size());
 
         Ok(())
     }
@@ -461,44 +586,147 @@ fn str_to_sized_array<const N: usize>(s: &str) -> [u8; N] {
     bytes
 }
 
+/// Reads a null padded string, anything after the first null is ignored
 fn buf_size_to_string(buf: &mut impl Buf, size: usize) -> Result<String> {
-    Ok(std::str::from_utf8(&buf.copy_to_bytes(size)[..])?
-        .trim_matches(char::from(0))
-        .to_string())
+    let bytes = buf.copy_to_bytes(size);
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(size);
+    Ok(std::str::from_utf8(&bytes[..end])?.to_string())
 }
 
 impl Arbitrary for Packet {
     fn arbitrary(g: &mut quickcheck::Gen) -> Self {
-        let options = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13];
-        let mut buff = BytesMut::with_capacity(MAX_PACKET_SIZE);
-
-        let enum_num = g.choose(&options).unwrap();
-        let size = match enum_num {
-            1 => 2,
-            2 => 0x38,
-            3 => 0x50,
-            4 => 0x42,
-            5 => 6,
-            6 => 6 + CLIENT_NAME_SIZE + 2,
-            7 => 0,
-            8 => COSTUME_NAME_SIZE * 2,
-            9 => 4,
-            10 => COSTUME_NAME_SIZE,
-            11 => STAGE_ID_SIZE + STAGE_CHANGE_NAME_SIZE + 2,
-            12 => 0,
-            13 => 2,
-            _ => 0,
-        };
-
-        for _ in 0..16 {
-            buff.put_u8(u8::arbitrary(g));
+        let mut id = [0; 16];
+        for byte in &mut id {
+            *byte = u8::arbitrary(g);
         }
-        buff.put_u16_le(*enum_num);
-        buff.put_u16_le(size as u16);
-        for _ in 0..size {
-            buff.put_u8(u8::arbitrary(g) % 128);
+        let mut packet = Packet::new(id.into(), PacketData::arbitrary(g));
+
+        // Sizes declared larger than needed are padding the decoder has to skip
+        if !matches!(packet.data, PacketData::Unhandled { .. }) {
+            let padding = u16::arbitrary(g) % 4;
+            packet.data_size = (packet.data_size + padding).min(MAX_DATA_SIZE as u16);
         }
+        packet
+    }
+}
+
+impl Arbitrary for PacketData {
+    fn arbitrary(g: &mut quickcheck::Gen) -> Self {
+        let f32 = |g: &mut quickcheck::Gen| f32::from(i16::arbitrary(g)) / 8.0;
+        let vector = |g: &mut quickcheck::Gen| Vector3::new(f32(g), f32(g), f32(g));
+        let string = |g: &mut quickcheck::Gen, max_len: usize| {
+            let len = usize::arbitrary(g) % (max_len + 1);
+            (0..len)
+                .map(|_| char::from(b' ' + u8::arbitrary(g) % 95))
+                .collect::<String>()
+        };
+        let protocol = |g: &mut quickcheck::Gen| {
+            Option::<(u16, u32)>::arbitrary(g).map(|(version, extensions)| ProtocolInfo {
+                version,
+                extensions: Extensions(extensions),
+            })
+        };
+
+        let p_type = *g
+            .choose(&[
+                PacketType::Unknown,
+                PacketType::ServerMessage,
+                PacketType::Ping,
+                PacketType::Init,
+                PacketType::Player,
+                PacketType::Cap,
+                PacketType::Game,
+                PacketType::Tag,
+                PacketType::Connect,
+                PacketType::Disconnect,
+                PacketType::Costume,
+                PacketType::Shine,
+                PacketType::Capture,
+                PacketType::ChangeStage,
+                PacketType::Command,
+                PacketType::UdpInit,
+                PacketType::HolePunch,
+            ])
+            .unwrap();
 
-        Packet::decode(&mut Cursor::new(buff)).unwrap()
+        match p_type {
+            PacketType::Unknown => {
+                let tag = match u16::arbitrary(g) {
+                    tag if PacketType::try_from(tag).is_ok() => 0,
+                    tag => tag,
+                };
+                let len = usize::arbitrary(g) % (MAX_DATA_SIZE + 1);
+                let data = (0..len).map(|_| u8::arbitrary(g)).collect();
+                PacketData::Unhandled { tag, data }
+            }
+            PacketType::Init => PacketData::Init {
+                max_players: u16::arbitrary(g),
+                protocol: protocol(g),
+            },
+            PacketType::Player => PacketData::Player {
+                pos: vector(g),
+                rot: Quaternion::new(f32(g), f32(g), f32(g), f32(g)),
+                animation_blend_weights: [f32(g), f32(g), f32(g), f32(g), f32(g), f32(g)],
+                act: u16::arbitrary(g),
+                sub_act: u16::arbitrary(g),
+            },
+            PacketType::Cap => PacketData::Cap {
+                pos: vector(g),
+                rot: Quaternion::new(f32(g), f32(g), f32(g), f32(g)),
+                cap_out: bool::arbitrary(g),
+                cap_anim: string(g, CAP_ANIM_SIZE),
+            },
+            PacketType::Game => PacketData::Game {
+                is_2d: bool::arbitrary(g),
+                scenario_num: u8::arbitrary(g),
+                stage: string(g, STAGE_GAME_NAME_SIZE),
+            },
+            PacketType::Tag => PacketData::Tag {
+                update_type: *g.choose(&[TagUpdate::Time, TagUpdate::State]).unwrap(),
+                is_it: bool::arbitrary(g),
+                seconds: u8::arbitrary(g),
+                minutes: u16::arbitrary(g),
+            },
+            PacketType::Connect => PacketData::Connect {
+                c_type: *g
+                    .choose(&[
+                        ConnectionType::FirstConnection,
+                        ConnectionType::Reconnecting,
+                    ])
+                    .unwrap(),
+                max_player: u16::arbitrary(g),
+                client_name: string(g, CLIENT_NAME_SIZE),
+                protocol: protocol(g),
+            },
+            PacketType::Disconnect => PacketData::Disconnect,
+            PacketType::Costume => PacketData::Costume(Costume {
+                body_name: string(g, COSTUME_NAME_SIZE),
+                cap_name: string(g, COSTUME_NAME_SIZE),
+            }),
+            PacketType::Shine => PacketData::Shine {
+                shine_id: i32::arbitrary(g),
+                is_grand: bool::arbitrary(g),
+            },
+            PacketType::Capture => PacketData::Capture {
+                model: string(g, COSTUME_NAME_SIZE),
+            },
+            PacketType::ChangeStage => PacketData::ChangeStage {
+                stage: string(g, STAGE_CHANGE_NAME_SIZE),
+                id: string(g, STAGE_ID_SIZE),
+                scenerio: i8::arbitrary(g),
+                sub_scenario: u8::arbitrary(g),
+            },
+            PacketType::Command => PacketData::Command,
+            PacketType::UdpInit => PacketData::UdpInit {
+                port: u16::arbitrary(g),
+            },
+            PacketType::HolePunch => PacketData::HolePunch,
+            PacketType::ServerMessage => PacketData::ServerMessage {
+                message: string(g, SERVER_MESSAGE_SIZE),
+            },
+            PacketType::Ping => PacketData::Ping {
+                nonce: u32::arbitrary(g),
+            },
+        }
     }
 }
@@ -0,0 +1,131 @@
+//! Optional protocol extensions on top of the stock SMOOnline packets.
+//!
+//! Clients that know about extensions append a [`ProtocolInfo`] to their
+//! `Connect` packet. The server answers with a second `Init` carrying the
+//! version and extensions both sides support, and only then sends extension
+//! packets. Stock clients never advertise anything, so they never see any of
+//! this.
+
+use std::ops::BitOr;
+
+use bytes::{Buf, BufMut};
+
+use super::encoding::{Decodable, Encodable};
+use crate::types::EncodingError;
+
+/// Newest protocol version the server speaks
+pub const PROTOCOL_VERSION: u16 = 1;
+/// Marks the trailing bytes of an `Init` or `Connect` as protocol info rather than padding
+pub const PROTOCOL_MAGIC: u32 = u32::from_le_bytes(*b"SMOO");
+pub const PROTOCOL_INFO_SIZE: usize = 4 + 2 + 4;
+
+/// Set of optional features a client or server supports
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Extensions(pub u32);
+
+impl Extensions {
+    pub const NONE: Self = Self(0);
+    /// Text messages from the server shown in game
+    pub const SERVER_MESSAGE: Self = Self(1 << 0);
+    /// Ping packets echoed back by the server to measure latency
+    pub const PING: Self = Self(1 << 1);
+
+    /// Everything this server implements
+    pub const SUPPORTED: Self = Self(Self::SERVER_MESSAGE.0 | Self::PING.0);
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn intersection(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+}
+
+impl BitOr for Extensions {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProtocolInfo {
+    pub version: u16,
+    pub extensions: Extensions,
+}
+
+impl ProtocolInfo {
+    /// What the server offers
+    pub fn server() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            extensions: Extensions::SUPPORTED,
+        }
+    }
+
+    /// The version and extensions both this and `other` support
+    pub fn negotiate(&self, other: &ProtocolInfo) -> ProtocolInfo {
+        ProtocolInfo {
+            version: self.version.min(other.version),
+            extensions: self.extensions.intersection(other.extensions),
+        }
+    }
+
+    /// Reads protocol info trailing a packet's stock fields, if there is any
+    pub(crate) fn decode_trailing(buf: &mut impl Buf) -> Option<ProtocolInfo> {
+        if buf.remaining() < PROTOCOL_INFO_SIZE {
+            return None;
+        }
+        ProtocolInfo::decode(buf).ok()
+    }
+}
+
+impl<R> Decodable<R> for ProtocolInfo
+where
+    R: Buf,
+{
+    fn decode(buf: &mut R) -> Result<Self, EncodingError> {
+        if buf.remaining() < PROTOCOL_INFO_SIZE {
+            return Err(EncodingError::NotEnoughData);
+        }
+        if buf.get_u32_le() != PROTOCOL_MAGIC {
+            return Err(EncodingError::CustomError);
+        }
+
+        Ok(ProtocolInfo {
+            version: buf.get_u16_le(),
+            extensions: Extensions(buf.get_u32_le()),
+        })
+    }
+}
+
+impl<W> Encodable<W> for ProtocolInfo
+where
+    W: BufMut,
+{
+    fn encode(&self, buf: &mut W) -> Result<(), EncodingError> {
+        buf.put_u32_le(PROTOCOL_MAGIC);
+        buf.put_u16_le(self.version);
+        buf.put_u32_le(self.extensions.0);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn negotiates_common_features() {
+        let client = ProtocolInfo {
+            version: 3,
+            extensions: Extensions::PING | Extensions(1 << 20),
+        };
+        let agreed = ProtocolInfo::server().negotiate(&client);
+        assert_eq!(agreed.version, PROTOCOL_VERSION);
+        assert_eq!(agreed.extensions, Extensions::PING);
+        assert!(!agreed.extensions.contains(Extensions::SERVER_MESSAGE));
+    }
+}
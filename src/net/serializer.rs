@@ -9,11 +9,41 @@ use serde::{
 
 use crate::types::EncodingError;
 
-struct SMOSerializer {
-    output: BytesMut,
+/// Serializes values in the game's wire format.
+///
+/// Integers and floats are little-endian and nothing is length prefixed:
+/// structs and tuples are their fields back to back, strings and byte slices
+/// are their raw bytes and `None` is omitted entirely. Fixed-width strings
+/// are written with [`fixed_str`](super::fixed_str).
+pub struct SMOSerializer<B: BufMut = BytesMut> {
+    pub output: B,
 }
 
-impl<'a> Serializer for &'a mut SMOSerializer {
+impl<B: BufMut> SMOSerializer<B> {
+    pub fn new(output: B) -> Self {
+        Self { output }
+    }
+}
+
+/// Appends the wire encoding of `value` to `output`
+pub fn to_buf<T, B>(value: &T, output: &mut B) -> Result<(), EncodingError>
+where
+    T: Serialize + ?Sized,
+    B: BufMut,
+{
+    value.serialize(&mut SMOSerializer::new(output))
+}
+
+pub fn to_bytes<T>(value: &T) -> Result<BytesMut, EncodingError>
+where
+    T: Serialize + ?Sized,
+{
+    let mut output = BytesMut::new();
+    to_buf(value, &mut output)?;
+    Ok(output)
+}
+
+impl<B: BufMut> Serializer for &mut SMOSerializer<B> {
     type Ok = ();
 
     type Error = EncodingError;
@@ -43,17 +73,17 @@ impl<'a> Serializer for &'a mut SMOSerializer {
     }
 
     fn serialize_i16(self, v: i16) -> Result<Self::Ok, Self::Error> {
-        self.output.put_i16(v);
+        self.output.put_i16_le(v);
         Ok(())
     }
 
     fn serialize_i32(self, v: i32) -> Result<Self::Ok, Self::Error> {
-        self.output.put_i32(v);
+        self.output.put_i32_le(v);
         Ok(())
     }
 
     fn serialize_i64(self, v: i64) -> Result<Self::Ok, Self::Error> {
-        self.output.put_i64(v);
+        self.output.put_i64_le(v);
         Ok(())
     }
 
@@ -63,32 +93,32 @@ impl<'a> Serializer for &'a mut SMOSerializer {
     }
 
     fn serialize_u16(self, v: u16) -> Result<Self::Ok, Self::Error> {
-        self.output.put_u16(v);
+        self.output.put_u16_le(v);
         Ok(())
     }
 
     fn serialize_u32(self, v: u32) -> Result<Self::Ok, Self::Error> {
-        self.output.put_u32(v);
+        self.output.put_u32_le(v);
         Ok(())
     }
 
     fn serialize_u64(self, v: u64) -> Result<Self::Ok, Self::Error> {
-        self.output.put_u64(v);
+        self.output.put_u64_le(v);
         Ok(())
     }
 
     fn serialize_f32(self, v: f32) -> Result<Self::Ok, Self::Error> {
-        self.output.put_f32(v);
+        self.output.put_f32_le(v);
         Ok(())
     }
 
     fn serialize_f64(self, v: f64) -> Result<Self::Ok, Self::Error> {
-        self.output.put_f64(v);
+        self.output.put_f64_le(v);
         Ok(())
     }
 
     fn serialize_char(self, v: char) -> Result<Self::Ok, Self::Error> {
-        self.serialize_str(&v.to_string())
+        self.serialize_u32(v.into())
     }
 
     fn serialize_str(self, v: &str) -> Result<Self::Ok, Self::Error> {
@@ -104,9 +134,9 @@ impl<'a> Serializer for &'a mut SMOSerializer {
         Ok(())
     }
 
-    fn serialize_some<T: ?Sized>(self, value: &T) -> Result<Self::Ok, Self::Error>
+    fn serialize_some<T>(self, value: &T) -> Result<Self::Ok, Self::Error>
     where
-        T: serde::Serialize,
+        T: ?Sized + serde::Serialize,
     {
         value.serialize(self)
     }
@@ -129,18 +159,18 @@ impl<'a> Serializer for &'a mut SMOSerializer {
         index.serialize(self)
     }
 
-    fn serialize_newtype_struct<T: ?Sized>(
+    fn serialize_newtype_struct<T>(
         self,
         _name: &'static str,
         value: &T,
     ) -> Result<Self::Ok, Self::Error>
     where
-        T: serde::Serialize,
+        T: ?Sized + serde::Serialize,
     {
         value.serialize(self)
     }
 
-    fn serialize_newtype_variant<T: ?Sized>(
+    fn serialize_newtype_variant<T>(
         self,
         _name: &'static str,
         variant_index: u32,
@@ -148,7 +178,7 @@ impl<'a> Serializer for &'a mut SMOSerializer {
         value: &T,
     ) -> Result<Self::Ok, Self::Error>
     where
-        T: serde::Serialize,
+        T: ?Sized + serde::Serialize,
     {
         let index: u16 = variant_index.try_into()?;
         index.serialize(&mut *self)?;
@@ -156,11 +186,11 @@ impl<'a> Serializer for &'a mut SMOSerializer {
     }
 
     fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
-        unimplemented!()
+        Ok(self)
     }
 
     fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Self::Error> {
-        unimplemented!()
+        Ok(self)
     }
 
     fn serialize_tuple_struct(
@@ -168,21 +198,23 @@ impl<'a> Serializer for &'a mut SMOSerializer {
         _name: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeTupleStruct, Self::Error> {
-        unimplemented!()
+        Ok(self)
     }
 
     fn serialize_tuple_variant(
         self,
         _name: &'static str,
-        _variant_index: u32,
+        variant_index: u32,
         _variant: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeTupleVariant, Self::Error> {
-        unimplemented!()
+        let index: u16 = variant_index.try_into()?;
+        index.serialize(&mut *self)?;
+        Ok(self)
     }
 
     fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
-        unimplemented!()
+        Err(EncodingError::CustomError)
     }
 
     fn serialize_struct(
@@ -190,7 +222,7 @@ impl<'a> Serializer for &'a mut SMOSerializer {
         _name: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeStruct, Self::Error> {
-        unimplemented!()
+        Ok(self)
     }
 
     fn serialize_struct_variant(
@@ -206,18 +238,14 @@ impl<'a> Serializer for &'a mut SMOSerializer {
     }
 }
 
-impl<'a> SerializeStruct for &'a mut SMOSerializer {
+impl<B: BufMut> SerializeStruct for &mut SMOSerializer<B> {
     type Ok = ();
 
     type Error = EncodingError;
 
-    fn serialize_field<T: ?Sized>(
-        &mut self,
-        _key: &'static str,
-        value: &T,
-    ) -> Result<(), Self::Error>
+    fn serialize_field<T>(&mut self, _key: &'static str, value: &T) -> Result<(), Self::Error>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         value.serialize(&mut **self)
     }
@@ -227,14 +255,14 @@ impl<'a> SerializeStruct for &'a mut SMOSerializer {
     }
 }
 
-impl<'a> SerializeSeq for &'a mut SMOSerializer {
+impl<B: BufMut> SerializeSeq for &mut SMOSerializer<B> {
     type Ok = ();
 
     type Error = EncodingError;
 
-    fn serialize_element<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error>
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), Self::Error>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         value.serialize(&mut **self)
     }
@@ -244,14 +272,14 @@ impl<'a> SerializeSeq for &'a mut SMOSerializer {
     }
 }
 
-impl<'a> SerializeTuple for &'a mut SMOSerializer {
+impl<B: BufMut> SerializeTuple for &mut SMOSerializer<B> {
     type Ok = ();
 
     type Error = EncodingError;
 
-    fn serialize_element<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error>
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), Self::Error>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         value.serialize(&mut **self)
     }
@@ -260,14 +288,14 @@ impl<'a> SerializeTuple for &'a mut SMOSerializer {
         Ok(())
     }
 }
-impl<'a> SerializeTupleStruct for &'a mut SMOSerializer {
+impl<B: BufMut> SerializeTupleStruct for &mut SMOSerializer<B> {
     type Ok = ();
 
     type Error = EncodingError;
 
-    fn serialize_field<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error>
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), Self::Error>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         value.serialize(&mut **self)
     }
@@ -276,14 +304,14 @@ impl<'a> SerializeTupleStruct for &'a mut SMOSerializer {
         Ok(())
     }
 }
-impl<'a> SerializeTupleVariant for &'a mut SMOSerializer {
+impl<B: BufMut> SerializeTupleVariant for &mut SMOSerializer<B> {
     type Ok = ();
 
     type Error = EncodingError;
 
-    fn serialize_field<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error>
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), Self::Error>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         value.serialize(&mut **self)
     }
@@ -292,41 +320,37 @@ impl<'a> SerializeTupleVariant for &'a mut SMOSerializer {
         Ok(())
     }
 }
-impl<'a> SerializeMap for &'a mut SMOSerializer {
+impl<B: BufMut> SerializeMap for &mut SMOSerializer<B> {
     type Ok = ();
 
     type Error = EncodingError;
 
-    fn serialize_key<T: ?Sized>(&mut self, _key: &T) -> Result<(), Self::Error>
+    fn serialize_key<T>(&mut self, _key: &T) -> Result<(), Self::Error>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
-        unimplemented!()
+        Err(EncodingError::CustomError)
     }
 
-    fn serialize_value<T: ?Sized>(&mut self, _value: &T) -> Result<(), Self::Error>
+    fn serialize_value<T>(&mut self, _value: &T) -> Result<(), Self::Error>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
-        unimplemented!()
+        Err(EncodingError::CustomError)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        unimplemented!()
+        Err(EncodingError::CustomError)
     }
 }
-impl<'a> SerializeStructVariant for &'a mut SMOSerializer {
+impl<B: BufMut> SerializeStructVariant for &mut SMOSerializer<B> {
     type Ok = ();
 
     type Error = EncodingError;
 
-    fn serialize_field<T: ?Sized>(
-        &mut self,
-        _key: &'static str,
-        value: &T,
-    ) -> Result<(), Self::Error>
+    fn serialize_field<T>(&mut self, _key: &'static str, value: &T) -> Result<(), Self::Error>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         value.serialize(&mut **self)
     }
@@ -1,104 +1,315 @@
 use std::{
-    io::Cursor,
-    net::{IpAddr, Ipv4Addr, SocketAddr},
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
 
-use bytes::{Buf, BufMut, BytesMut};
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt, BufWriter},
-    net::{TcpStream, UdpSocket},
-};
+use bytes::{BufMut, Bytes, BytesMut};
+use tokio::{net::UdpSocket, sync::mpsc};
 
 use crate::{
-    net::{encoding::Decodable, encoding::Encodable, Packet, MAX_PACKET_SIZE},
-    types::{EncodingError, Result, SMOError},
+    guid::Guid,
+    net::{
+        encoding::{Decodable, Encodable},
+        Packet, PacketData, PacketHeader, PacketType, RawPacket, HEADER_SIZE, MAX_PACKET_SIZE,
+    },
+    status::ConnectionMode,
+    types::{Result, SMOError},
 };
 
+/// How often hole punches are sent while punching and as keepalives after,
+/// well below the usual NAT mapping timeout of 30 seconds
+pub const HOLE_PUNCH_INTERVAL: Duration = Duration::from_secs(5);
+/// Silence after which a udp client falls back to tcp
+pub const UDP_TIMEOUT: Duration = Duration::from_secs(15);
+/// Weight of the latest keepalive interval in the loss estimate
+const LOSS_SMOOTHING: f32 = 0.25;
+
+/// How many received datagrams can wait for a client before new ones are dropped
+pub const DATAGRAM_QUEUE_SIZE: usize = 64;
+
+/// A datagram received on a udp socket, along with who sent it
+#[derive(Debug, Clone)]
+pub struct Datagram {
+    pub addr: SocketAddr,
+    pub data: Bytes,
+}
+
+/// Counts of received datagrams that were thrown away
+#[derive(Debug, Default)]
+pub struct UdpStats {
+    /// Datagrams nobody was ready for, like ones arriving before the hole punch
+    pub dropped: AtomicU64,
+    /// Datagrams from an unexpected address or carrying another player's guid
+    pub foreign: AtomicU64,
+    /// Datagrams too large for a packet or ending in the middle of one
+    pub truncated: AtomicU64,
+}
+
+impl UdpStats {
+    pub(crate) fn count(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl std::fmt::Display for UdpStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} dropped, {} foreign, {} truncated",
+            self.dropped.load(Ordering::Relaxed),
+            self.foreign.load(Ordering::Relaxed),
+            self.truncated.load(Ordering::Relaxed)
+        )
+    }
+}
+
 #[derive(Debug)]
 pub enum UdpSenderStatus {
     Pending(IpAddr),
+    /// Sending hole punches to the client until one comes back
+    Punching(SocketAddr),
     Connected(SocketAddr),
 }
+
+/// One client's side of a udp socket. Datagrams are read off the socket
+/// elsewhere, usually by a [`UdpMux`](super::udp_mux::UdpMux) shared by all
+/// clients, and handed over through a channel.
 #[derive(Debug)]
 pub struct UdpConnection {
-    pub socket: UdpSocket,
+    pub socket: Arc<UdpSocket>,
+    pub datagrams: mpsc::Receiver<Datagram>,
+    /// Guid every received packet has to carry, `None` to accept any
+    pub guid: Option<Guid>,
+    pub stats: Arc<UdpStats>,
     pub buff: BytesMut,
     pub send_addr: UdpSenderStatus,
+    pub last_recv: Option<Instant>,
+    /// Share of recent keepalive intervals in which nothing arrived
+    pub loss: f32,
+    recv_since_check: bool,
+    was_connected: bool,
+    mode_changed: bool,
 }
 
 impl UdpConnection {
-    pub fn new(stream: UdpSocket, addr: IpAddr) -> Self {
+    pub fn new(
+        socket: Arc<UdpSocket>,
+        datagrams: mpsc::Receiver<Datagram>,
+        stats: Arc<UdpStats>,
+        guid: Option<Guid>,
+        addr: IpAddr,
+    ) -> Self {
         UdpConnection {
-            socket: stream,
+            socket,
+            datagrams,
+            guid,
+            stats,
             buff: BytesMut::with_capacity(1024),
             send_addr: UdpSenderStatus::Pending(addr),
+            last_recv: None,
+            loss: 0.0,
+            recv_since_check: false,
+            was_connected: false,
+            mode_changed: false,
         }
     }
 
+    /// A connection owning its socket, talking to a single peer
     pub fn from_connection(stream: UdpSocket, addr: SocketAddr) -> Self {
-        UdpConnection {
-            socket: stream,
-            buff: BytesMut::with_capacity(1024),
-            send_addr: UdpSenderStatus::Connected(addr),
-        }
-    }
+        let socket = Arc::new(stream);
+        let stats = Arc::new(UdpStats::default());
+        let (to_conn, datagrams) = mpsc::channel(DATAGRAM_QUEUE_SIZE);
+        tokio::spawn(forward_datagrams(socket.clone(), to_conn, stats.clone()));
 
-    pub fn parse_packet(&mut self) -> Result<Option<Packet>> {
-        let mut buf = Cursor::new(&self.buff[..]);
-        match Packet::check(&mut buf) {
-            Ok(_) => {
-                let len = buf.position() as usize;
-
-                buf.set_position(0);
+        let mut conn = Self::new(socket, datagrams, stats, None, addr.ip());
+        conn.connect(addr);
+        conn
+    }
 
-                let packet = Packet::decode(&mut buf)?;
-                self.buff.advance(len);
+    pub fn parse_frame(&mut self) -> Result<Option<RawPacket>> {
+        Ok(RawPacket::split_from(&mut self.buff)?)
+    }
 
-                Ok(Some(packet))
-            }
-            Err(EncodingError::NotEnoughData) => Ok(None),
-            Err(e) => Err(e.into()),
+    pub fn parse_packet(&mut self) -> Result<Option<Packet>> {
+        match self.parse_frame()? {
+            Some(frame) => Ok(Some(frame.decode()?)),
+            None => Ok(None),
         }
     }
 
     pub fn is_client_udp(&self) -> bool {
+        matches!(self.send_addr, UdpSenderStatus::Connected(_))
+    }
+
+    pub fn mode(&self) -> ConnectionMode {
         match self.send_addr {
-            UdpSenderStatus::Connected(_) => true,
-            _ => false,
+            UdpSenderStatus::Connected(_) => ConnectionMode::Udp,
+            _ if self.was_connected => ConnectionMode::TcpFallback,
+            _ => ConnectionMode::Tcp,
+        }
+    }
+
+    /// Whether the client switched between tcp and udp since this was last called
+    pub fn take_mode_change(&mut self) -> bool {
+        std::mem::take(&mut self.mode_changed)
+    }
+
+    fn connect(&mut self, addr: SocketAddr) {
+        self.send_addr = UdpSenderStatus::Connected(addr);
+        self.last_recv = Some(Instant::now());
+        self.loss = 0.0;
+        self.recv_since_check = true;
+        self.was_connected = true;
+        self.mode_changed = true;
+    }
+
+    /// Updates the loss estimate and falls back to tcp once nothing arrived
+    /// for [`UDP_TIMEOUT`]. Hole punches keep going out afterwards, so the
+    /// client is upgraded again as soon as its traffic gets through.
+    pub fn check_liveness(&mut self, now: Instant) {
+        let addr = match self.send_addr {
+            UdpSenderStatus::Connected(addr) => addr,
+            _ => return,
+        };
+
+        let missed = if std::mem::take(&mut self.recv_since_check) {
+            0.0
+        } else {
+            1.0
+        };
+        self.loss += (missed - self.loss) * LOSS_SMOOTHING;
+
+        let silent = self
+            .last_recv
+            .map(|last| now.duration_since(last) >= UDP_TIMEOUT)
+            .unwrap_or(true);
+        if silent {
+            tracing::warn!(
+                "No udp from {} for {:?} (loss {:.0}%), falling back to tcp",
+                addr,
+                UDP_TIMEOUT,
+                self.loss * 100.0
+            );
+            self.send_addr = UdpSenderStatus::Punching(addr);
+            self.mode_changed = true;
         }
     }
 
     pub fn set_client_port(&mut self, port: u16) {
         let new_addr = match self.send_addr {
             UdpSenderStatus::Pending(ip) => SocketAddr::new(ip, port),
-            UdpSenderStatus::Connected(addr) => {
+            UdpSenderStatus::Punching(addr) | UdpSenderStatus::Connected(addr) => {
                 let ip = addr.ip();
                 SocketAddr::new(ip, port)
             }
         };
-        self.send_addr = UdpSenderStatus::Connected(new_addr)
+        self.connect(new_addr)
+    }
+
+    /// Starts punching towards the port the client says it listens on. The
+    /// client only counts as udp once a hole punch from it gets through.
+    pub fn begin_hole_punch(&mut self, port: u16) {
+        let ip = match self.send_addr {
+            UdpSenderStatus::Pending(ip) => ip,
+            UdpSenderStatus::Punching(addr) | UdpSenderStatus::Connected(addr) => addr.ip(),
+        };
+        self.send_addr = UdpSenderStatus::Punching(SocketAddr::new(ip, port))
+    }
+
+    /// Sends a hole punch to the client, doing nothing before it sent its udp port
+    pub async fn send_hole_punch(&mut self) -> Result<()> {
+        let addr = match self.send_addr {
+            UdpSenderStatus::Pending(_) => return Ok(()),
+            UdpSenderStatus::Punching(addr) | UdpSenderStatus::Connected(addr) => addr,
+        };
+
+        let mut buff = BytesMut::with_capacity(HEADER_SIZE);
+        Packet::new(Guid::default(), PacketData::HolePunch).encode(&mut buff)?;
+        self.socket.send_to(&buff[..], addr).await?;
+        Ok(())
     }
 
     pub async fn read_packet(&mut self) -> Result<Packet> {
+        Ok(self.read_frame().await?.decode()?)
+    }
+
+    pub async fn read_frame(&mut self) -> Result<RawPacket> {
         loop {
-            if let Some(packet) = self.parse_packet()? {
-                return Ok(packet);
+            if let Some(frame) = self.parse_frame()? {
+                return Ok(frame);
             }
             self.read_socket().await?
         }
     }
 
+    async fn recv_datagram(&mut self) -> Datagram {
+        match self.datagrams.recv().await {
+            Some(datagram) => datagram,
+            // Nothing will arrive anymore, tcp is all that's left
+            None => futures::future::pending().await,
+        }
+    }
+
+    /// Whether a datagram holds only whole packets from the expected sender,
+    /// counting it as thrown away if not
+    fn check_datagram(&self, data: &[u8]) -> bool {
+        match check_packets(data, self.guid) {
+            Ok(()) => true,
+            Err(DatagramError::Foreign) => {
+                tracing::debug!("Dropping udp packet with a foreign guid");
+                UdpStats::count(&self.stats.foreign);
+                false
+            }
+            Err(DatagramError::Truncated) => {
+                UdpStats::count(&self.stats.truncated);
+                false
+            }
+        }
+    }
+
     pub async fn read_socket(&mut self) -> Result<()> {
-        let mut buff = vec![0u8; 100];
+        match self.send_addr {
+            UdpSenderStatus::Connected(expected_addr) => {
+                let Datagram { addr, data } = self.recv_datagram().await;
+                if addr != expected_addr {
+                    UdpStats::count(&self.stats.foreign);
+                } else if self.check_datagram(&data) {
+                    self.last_recv = Some(Instant::now());
+                    self.recv_since_check = true;
+                    self.buff.put_slice(&data);
+                }
+            }
+            UdpSenderStatus::Punching(expected_addr) => {
+                let Datagram { addr, data } = self.recv_datagram().await;
+                if addr.ip() != expected_addr.ip() {
+                    UdpStats::count(&self.stats.foreign);
+                    return Ok(());
+                }
+                if !self.check_datagram(&data) {
+                    return Ok(());
+                }
 
-        if let UdpSenderStatus::Connected(expected_addr) = self.send_addr {
-            let (read_amount, addr) = self.socket.recv_from(&mut buff).await?;
-            if addr == expected_addr {
-                self.buff.put_slice(&buff[..read_amount]);
+                // NAT may have rewritten the port, so the punch is trusted to
+                // come from any port on the client's address. After a fallback
+                // any traffic from the old address proves udp works again.
+                if is_hole_punch(&data) || (addr == expected_addr && self.was_connected) {
+                    tracing::debug!("Udp from {} got through", addr);
+                    self.connect(addr);
+                    self.buff.put_slice(&data);
+                    // Answer so the client's side of the round trip completes too
+                    self.send_hole_punch().await?;
+                } else {
+                    UdpStats::count(&self.stats.dropped);
+                }
+            }
+            UdpSenderStatus::Pending(_) => {
+                // Never resolve as connection isnt ready
+                futures::future::pending().await
             }
-        } else {
-            // Never resolve as connection isnt ready
-            futures::future::pending().await
         }
 
         Ok(())
@@ -109,15 +320,226 @@ impl UdpConnection {
         if let UdpSenderStatus::Connected(send_addr) = self.send_addr {
             let mut buff = BytesMut::with_capacity(MAX_PACKET_SIZE);
             packet.encode(&mut buff)?;
-
-            let mut amount = 0;
-            while amount < buff.len() {
-                let last_write = self.socket.send_to(&buff[..], send_addr).await;
-                amount += last_write.unwrap();
-            }
+            self.socket.send_to(&buff[..], send_addr).await?;
             Ok(())
         } else {
             Err(SMOError::UdpNotInit)
         }
     }
+
+    pub async fn write_frame(&mut self, frame: &RawPacket) -> Result<()> {
+        match self.send_addr {
+            UdpSenderStatus::Connected(send_addr) => {
+                self.socket.send_to(&frame.frame, send_addr).await?;
+                Ok(())
+            }
+            UdpSenderStatus::Pending(_) | UdpSenderStatus::Punching(_) => Err(SMOError::UdpNotInit),
+        }
+    }
+}
+
+/// Receives the next datagram that fits in a packet. The buffer has a byte
+/// to spare, so anything filling it was cut off by the socket.
+pub(crate) async fn recv_datagram(
+    socket: &UdpSocket,
+    buff: &mut [u8; MAX_PACKET_SIZE + 1],
+    stats: &UdpStats,
+) -> Datagram {
+    loop {
+        let (read_amount, addr) = match socket.recv_from(buff).await {
+            Ok(received) => received,
+            Err(e) => {
+                // Windows reports icmp port unreachable errors from
+                // earlier sends here, which says nothing about this socket
+                tracing::debug!("Failed to read udp socket: {}", e);
+                continue;
+            }
+        };
+
+        if read_amount > MAX_PACKET_SIZE {
+            UdpStats::count(&stats.truncated);
+            continue;
+        }
+        let data = Bytes::copy_from_slice(&buff[..read_amount]);
+        return Datagram { addr, data };
+    }
+}
+
+/// Reads every datagram arriving on `socket` into `to_conn`
+async fn forward_datagrams(
+    socket: Arc<UdpSocket>,
+    to_conn: mpsc::Sender<Datagram>,
+    stats: Arc<UdpStats>,
+) {
+    let mut buff = [0; MAX_PACKET_SIZE + 1];
+    loop {
+        let datagram = recv_datagram(&socket, &mut buff, &stats).await;
+        if to_conn.send(datagram).await.is_err() {
+            break;
+        }
+    }
+}
+
+enum DatagramError {
+    Foreign,
+    Truncated,
+}
+
+/// Walks the packets in a datagram, which has to end on a packet boundary
+fn check_packets(mut data: &[u8], guid: Option<Guid>) -> std::result::Result<(), DatagramError> {
+    while !data.is_empty() {
+        let header = PacketHeader::decode(&mut data).map_err(|_| DatagramError::Truncated)?;
+        if guid.is_some_and(|guid| guid != header.id) {
+            return Err(DatagramError::Foreign);
+        }
+        let size = usize::from(header.data_size);
+        if data.len() < size {
+            return Err(DatagramError::Truncated);
+        }
+        data = &data[size..];
+    }
+    Ok(())
+}
+
+fn is_hole_punch(mut data: &[u8]) -> bool {
+    PacketHeader::decode(&mut data)
+        .map(|header| header.packet_type() == Some(PacketType::HolePunch))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::net::udp_mux::UdpMux;
+
+    async fn recv_type(socket: &UdpSocket) -> Option<PacketType> {
+        let mut buff = [0; MAX_PACKET_SIZE];
+        let (len, _) = socket.recv_from(&mut buff).await.unwrap();
+        PacketHeader::decode(&mut &buff[..len])
+            .unwrap()
+            .packet_type()
+    }
+
+    #[tokio::test]
+    async fn hole_punch_round_trip() {
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let mux = UdpMux::bind((localhost, 0).into(), Default::default())
+            .await
+            .unwrap();
+        let server_addr = mux.local_addr().unwrap();
+        let client = UdpSocket::bind((localhost, 0)).await.unwrap();
+        let client_port = client.local_addr().unwrap().port();
+
+        let mut conn = mux.connection([1; 16].into(), localhost).await;
+        tokio::spawn(mux.run());
+        conn.begin_hole_punch(client_port);
+        conn.send_hole_punch().await.unwrap();
+        assert!(!conn.is_client_udp());
+        assert_eq!(recv_type(&client).await, Some(PacketType::HolePunch));
+
+        let mut buff = BytesMut::new();
+        let disconnect = Packet::new([1; 16].into(), PacketData::Disconnect);
+        disconnect.encode(&mut buff).unwrap();
+        client.send_to(&buff, server_addr).await.unwrap();
+        let punch = Packet::new([1; 16].into(), PacketData::HolePunch);
+        buff.clear();
+        punch.encode(&mut buff).unwrap();
+        client.send_to(&buff, server_addr).await.unwrap();
+
+        // Anything before the punch is dropped
+        assert_eq!(conn.read_packet().await.unwrap(), punch);
+        assert!(conn.is_client_udp());
+        assert_eq!(recv_type(&client).await, Some(PacketType::HolePunch));
+        assert_eq!(conn.stats.dropped.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn rejects_spoofed_and_truncated_datagrams() {
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let mux = UdpMux::bind((localhost, 0).into(), Default::default())
+            .await
+            .unwrap();
+        let server_addr = mux.local_addr().unwrap();
+        let client = UdpSocket::bind((localhost, 0)).await.unwrap();
+        let other = UdpSocket::bind((localhost, 0)).await.unwrap();
+
+        let mut conn = mux.connection([1; 16].into(), localhost).await;
+        conn.set_client_port(client.local_addr().unwrap().port());
+        tokio::spawn(mux.run());
+
+        let encode = |packets: &[&Packet]| {
+            let mut buff = BytesMut::new();
+            for packet in packets {
+                packet.encode(&mut buff).unwrap();
+            }
+            buff
+        };
+        let own = Packet::new([1; 16].into(), PacketData::Disconnect);
+        let spoofed = Packet::new([2; 16].into(), PacketData::Disconnect);
+        let shine = Packet::new(
+            [1; 16].into(),
+            PacketData::Shine {
+                shine_id: 3,
+                is_grand: false,
+            },
+        );
+
+        let datagrams = [
+            (&other, encode(&[&own])),
+            (&client, encode(&[&own, &spoofed])),
+            (&client, encode(&[&shine]).split_to(HEADER_SIZE + 2)),
+            (&client, BytesMut::zeroed(MAX_PACKET_SIZE + 10)),
+            (&client, encode(&[&shine])),
+        ];
+        for (socket, datagram) in datagrams {
+            socket.send_to(&datagram, server_addr).await.unwrap();
+        }
+
+        assert_eq!(conn.read_packet().await.unwrap(), shine);
+        assert_eq!(conn.stats.foreign.load(Ordering::Relaxed), 2);
+        assert_eq!(conn.stats.truncated.load(Ordering::Relaxed), 2);
+        assert_eq!(conn.stats.dropped.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_tcp_and_recovers() {
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let server = UdpSocket::bind((localhost, 0)).await.unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let client = UdpSocket::bind((localhost, 0)).await.unwrap();
+        let client_addr = client.local_addr().unwrap();
+
+        let mut conn = UdpConnection::from_connection(server, client_addr);
+        assert!(conn.take_mode_change());
+        assert_eq!(conn.mode(), ConnectionMode::Udp);
+
+        let now = Instant::now();
+        conn.check_liveness(now + HOLE_PUNCH_INTERVAL);
+        assert_eq!(conn.mode(), ConnectionMode::Udp);
+        conn.check_liveness(now + UDP_TIMEOUT);
+        assert!(conn.loss > 0.0);
+        assert!(conn.take_mode_change());
+        assert_eq!(conn.mode(), ConnectionMode::TcpFallback);
+        assert!(!conn.is_client_udp());
+
+        let mut buff = BytesMut::new();
+        let tag = Packet::new(
+            [1; 16].into(),
+            PacketData::Tag {
+                update_type: crate::net::TagUpdate::State,
+                is_it: true,
+                seconds: 0,
+                minutes: 0,
+            },
+        );
+        tag.encode(&mut buff).unwrap();
+        client.send_to(&buff, server_addr).await.unwrap();
+
+        assert_eq!(conn.read_packet().await.unwrap(), tag);
+        assert!(conn.take_mode_change());
+        assert_eq!(conn.mode(), ConnectionMode::Udp);
+        assert_eq!(conn.loss, 0.0);
+    }
 }
@@ -0,0 +1,177 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use tokio::{
+    net::UdpSocket,
+    sync::{
+        mpsc::{self, error::TrySendError},
+        RwLock,
+    },
+};
+
+use crate::{
+    guid::Guid,
+    net::{
+        encoding::Decodable,
+        udp_conn::{recv_datagram, Datagram, UdpConnection, UdpStats, DATAGRAM_QUEUE_SIZE},
+        PacketHeader, MAX_PACKET_SIZE,
+    },
+    types::Result,
+};
+
+type Routes = HashMap<Guid, mpsc::Sender<Datagram>>;
+
+/// The server's single udp socket, shared by all clients.
+///
+/// Every packet starts with its sender's guid, so datagrams are routed to the
+/// connection registered for that guid, which then checks they came from its
+/// client's address and that every packet in them carries the same guid.
+#[derive(Debug, Clone)]
+pub struct UdpMux {
+    socket: Arc<UdpSocket>,
+    routes: Arc<RwLock<Routes>>,
+    stats: Arc<UdpStats>,
+}
+
+impl UdpMux {
+    /// Binds the socket, counting datagrams it throws away in `stats`
+    pub async fn bind(addr: SocketAddr, stats: Arc<UdpStats>) -> Result<Self> {
+        let socket = UdpSocket::bind(addr).await?;
+        Ok(Self {
+            socket: Arc::new(socket),
+            routes: Arc::new(RwLock::new(Routes::new())),
+            stats,
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    /// Registers a connection for the client with `guid`, replacing the one
+    /// of a previous session
+    pub async fn connection(&self, guid: Guid, ip: IpAddr) -> UdpConnection {
+        let (to_conn, datagrams) = mpsc::channel(DATAGRAM_QUEUE_SIZE);
+        self.routes.write().await.insert(guid, to_conn);
+        UdpConnection::new(
+            self.socket.clone(),
+            datagrams,
+            self.stats.clone(),
+            Some(guid),
+            ip,
+        )
+    }
+
+    /// Removes the route of a finished connection for `guid`, unless the
+    /// client already reconnected and registered a new one
+    pub async fn unregister(&self, guid: Guid, conn: UdpConnection) {
+        // Dropping the connection closes its channel, which tells its route
+        // apart from one of a newer session
+        drop(conn);
+        remove_closed(&mut *self.routes.write().await, guid);
+    }
+
+    pub async fn run(self) -> Result<()> {
+        let mut buff = [0; MAX_PACKET_SIZE + 1];
+        loop {
+            let datagram = recv_datagram(&self.socket, &mut buff, &self.stats).await;
+            let guid = match PacketHeader::decode(&mut &datagram.data[..]) {
+                Ok(header) => header.id,
+                Err(_) => {
+                    UdpStats::count(&self.stats.truncated);
+                    continue;
+                }
+            };
+            self.route(guid, datagram).await;
+        }
+    }
+
+    async fn route(&self, guid: Guid, datagram: Datagram) {
+        let routes = self.routes.read().await;
+        let to_conn = match routes.get(&guid) {
+            Some(to_conn) => to_conn,
+            None => {
+                UdpStats::count(&self.stats.foreign);
+                return;
+            }
+        };
+
+        match to_conn.try_send(datagram) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                tracing::trace!("Udp queue of {} full", guid);
+                UdpStats::count(&self.stats.dropped);
+            }
+            Err(TrySendError::Closed(_)) => {
+                UdpStats::count(&self.stats.dropped);
+                drop(routes);
+                remove_closed(&mut *self.routes.write().await, guid);
+            }
+        }
+    }
+}
+
+/// Removes the route for `guid` if its connection is gone. The client may
+/// have reconnected in the meantime, in which case the route is kept.
+fn remove_closed(routes: &mut Routes, guid: Guid) {
+    if routes.get(&guid).is_some_and(|to_conn| to_conn.is_closed()) {
+        routes.remove(&guid);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::net::{encoding::Encodable, Packet, PacketData};
+
+    #[tokio::test]
+    async fn routes_by_guid() {
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let mux = UdpMux::bind((localhost, 0).into(), Default::default())
+            .await
+            .unwrap();
+        let mux_addr = mux.local_addr().unwrap();
+        let mut first = mux.connection([1; 16].into(), localhost).await;
+        let mut second = mux.connection([2; 16].into(), localhost).await;
+        tokio::spawn(mux.run());
+
+        let client = UdpSocket::bind((localhost, 0)).await.unwrap();
+        for id in [[3; 16], [2; 16], [1; 16]] {
+            let mut buff = bytes::BytesMut::new();
+            Packet::new(id.into(), PacketData::HolePunch)
+                .encode(&mut buff)
+                .unwrap();
+            client.send_to(&buff, mux_addr).await.unwrap();
+        }
+
+        let datagram = first.datagrams.recv().await.unwrap();
+        assert_eq!(datagram.addr, client.local_addr().unwrap());
+        assert_eq!(&datagram.data[..16], &[1; 16]);
+        let datagram = second.datagrams.recv().await.unwrap();
+        assert_eq!(&datagram.data[..16], &[2; 16]);
+        assert!(first.datagrams.try_recv().is_err());
+        assert!(second.datagrams.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn unregister_keeps_newer_route() {
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let mux = UdpMux::bind((localhost, 0).into(), Default::default())
+            .await
+            .unwrap();
+        let guid: Guid = [1; 16].into();
+
+        let old = mux.connection(guid, localhost).await;
+        let new = mux.connection(guid, localhost).await;
+        mux.unregister(guid, old).await;
+        assert!(mux.routes.read().await.contains_key(&guid));
+
+        mux.unregister(guid, new).await;
+        assert!(mux.routes.read().await.is_empty());
+    }
+}
@@ -0,0 +1,29 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use serde::Serialize;
+
+use crate::types::Result;
+
+/// Writes `value` as json to a temporary file and renames it over `path`, so
+/// readers and crashes mid-write never see a truncated file
+pub fn write_json_atomic(path: &Path, value: &impl Serialize, pretty: bool) -> Result<()> {
+    let tmp_path = path.with_extension("tmp");
+
+    let file = File::create(&tmp_path)?;
+    let mut writer = BufWriter::new(file);
+    if pretty {
+        serde_json::to_writer_pretty(&mut writer, value)?;
+    } else {
+        serde_json::to_writer(&mut writer, value)?;
+    }
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+    drop(writer);
+
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
@@ -1,38 +1,43 @@
 use crate::types::Result;
-use std::net::SocketAddr;
-use tokio::{
-    net::{TcpListener, TcpStream},
-    sync::mpsc,
+use std::{net::SocketAddr, sync::Arc};
+use tokio::{net::TcpListener, sync::mpsc};
+
+use crate::{
+    client::Client,
+    cmds::Command,
+    net::{udp_conn::UdpStats, udp_mux::UdpMux},
+    settings::SyncSettings,
 };
 
-use crate::{client::Client, cmds::Command, settings::SyncSettings};
-
 pub struct Server {
     pub to_coord: mpsc::Sender<Command>,
     pub settings: SyncSettings,
     pub udp_port: u16,
+    pub udp_stats: Arc<UdpStats>,
 }
 
 impl Server {
     pub async fn listen_for_clients(self, addr: SocketAddr) -> Result<()> {
         let listener = TcpListener::bind(addr).await?;
-        let base_udp_port = self.udp_port;
-        let mut udp_offset = 0;
+        let udp = UdpMux::bind(
+            SocketAddr::new(addr.ip(), self.udp_port),
+            self.udp_stats.clone(),
+        )
+        .await?;
+        tracing::info!("Binding udp port to {}", udp.local_addr()?);
+        tokio::spawn(udp.clone().run());
 
         loop {
             let (socket, _) = listener.accept().await?;
 
             let to_coord = self.to_coord.clone();
             let settings = self.settings.clone();
-            let udp_port = base_udp_port + udp_offset;
-            udp_offset += 1;
-            udp_offset %= 2;
+            let udp = udp.clone();
 
             tracing::info!("New client attempting to connect");
 
             tokio::spawn(async move {
-                let cli_result =
-                    Client::initialize_client(socket, to_coord, udp_port, settings).await;
+                let cli_result = Client::initialize_client(socket, to_coord, udp, settings).await;
 
                 if let Err(e) = cli_result {
                     tracing::warn!("Client failed to begin: {}", e)
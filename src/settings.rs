@@ -1,12 +1,14 @@
-use std::{collections::HashSet, net::IpAddr, sync::Arc};
+use std::{collections::HashSet, fs::File, io::BufReader, net::IpAddr, path::Path, sync::Arc};
 
 use serde::{Deserialize, Serialize};
 use tokio::sync::RwLock;
 
-use crate::guid::Guid;
+use crate::{guid::Guid, persist::write_json_atomic, types::Result};
+
+pub const SETTINGS_PATH: &str = "./settings.json";
 
 pub type SyncSettings = Arc<RwLock<Settings>>;
-#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct Settings {
     pub server: ServerSettings,
@@ -15,20 +17,25 @@ pub struct Settings {
     pub ban_list: BanListSettings,
     pub discord: DiscordSettings,
     pub persist_shines: PersistShine,
+    #[serde(default)]
+    pub shine_sync: ShineSyncSettings,
     // pub max_players: u16,
     // pub banned_players: HashSet<Guid>,
     // pub banned_ips: HashSet<IpAddr>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct ServerSettings {
     pub address: IpAddr,
     pub port: u16,
     pub max_players: u16,
+    /// Reload settings.json automatically whenever it changes on disk
+    #[serde(default)]
+    pub watch_settings: bool,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct FlipSettings {
     pub enabled: bool,
@@ -36,21 +43,22 @@ pub struct FlipSettings {
     pub pov: FlipPovSettings,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub enum FlipPovSettings {
+    #[default]
     Both,
     Player,
     Others,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct ScenarioSettings {
     pub merge_enabled: bool,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct BanListSettings {
     pub enabled: bool,
@@ -58,27 +66,44 @@ pub struct BanListSettings {
     pub ips: HashSet<IpAddr>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct DiscordSettings {
     pub token: Option<String>,
     pub prefix: String,
     pub log_channel: Option<String>,
+    /// Ids of the users allowed to run commands
+    #[serde(default)]
+    pub admin_users: HashSet<String>,
+    /// Ids of the roles whose members are allowed to run commands
+    #[serde(default)]
+    pub admin_roles: HashSet<String>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct PersistShine {
     pub enabled: bool,
     pub filename: String,
 }
 
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct ShineSyncSettings {
+    pub enabled: bool,
+    /// Players that neither receive nor share moons, e.g. racers
+    pub excluded_players: HashSet<Guid>,
+    /// Moons that are never synced because receiving them early softlocks the story
+    pub excluded_shines: HashSet<i32>,
+}
+
 impl Default for ServerSettings {
     fn default() -> Self {
         Self {
             address: "0.0.0.0".parse().unwrap(),
             port: 1027,
             max_players: 8,
+            watch_settings: false,
         }
     }
 }
@@ -93,30 +118,24 @@ impl Default for FlipSettings {
     }
 }
 
-impl Default for ScenarioSettings {
-    fn default() -> Self {
-        Self {
-            merge_enabled: false,
-        }
-    }
-}
-
-impl Default for BanListSettings {
+impl Default for DiscordSettings {
     fn default() -> Self {
         Self {
-            enabled: false,
-            players: Default::default(),
-            ips: Default::default(),
+            token: Default::default(),
+            prefix: "$".to_string(),
+            log_channel: Default::default(),
+            admin_users: Default::default(),
+            admin_roles: Default::default(),
         }
     }
 }
 
-impl Default for DiscordSettings {
+impl Default for ShineSyncSettings {
     fn default() -> Self {
         Self {
-            token: Default::default(),
-            prefix: "$".to_string(),
-            log_channel: Default::default(),
+            enabled: true,
+            excluded_players: Default::default(),
+            excluded_shines: HashSet::from([496]),
         }
     }
 }
@@ -130,8 +149,27 @@ impl Default for PersistShine {
     }
 }
 
-impl Default for FlipPovSettings {
-    fn default() -> Self {
-        Self::Both
+pub fn read_settings() -> Result<Settings> {
+    let file = File::open(SETTINGS_PATH)?;
+    let reader = BufReader::new(file);
+    let settings = serde_json::from_reader(reader)?;
+
+    Ok(settings)
+}
+
+pub fn save_settings(settings: &Settings) -> Result<()> {
+    tracing::debug!("Saving settings");
+    write_json_atomic(Path::new(SETTINGS_PATH), settings, true)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn partial_shine_sync_block() {
+        let shine_sync: ShineSyncSettings = serde_json::from_str(r#"{"Enabled": false}"#).unwrap();
+        assert!(!shine_sync.enabled);
+        assert_eq!(shine_sync.excluded_shines, HashSet::from([496]));
     }
 }
@@ -0,0 +1,167 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::BufReader,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use crate::{guid::Guid, persist::write_json_atomic, types::Result};
+
+pub type ShineBag = HashMap<i32, ShineRecord>;
+pub type SyncShineBag = Arc<RwLock<ShineBag>>;
+
+/// A collected moon and who got it first
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ShineRecord {
+    pub id: i32,
+    /// Multi moons count as three and have to be replayed as such
+    #[serde(default)]
+    pub is_grand: bool,
+    /// Unknown for moons loaded from an old shine file
+    pub collector: Option<Guid>,
+    pub collected_at: Option<SystemTime>,
+    /// Stage the collector was in when they got the moon
+    #[serde(default)]
+    pub stage: Option<String>,
+}
+
+impl ShineRecord {
+    pub fn new(id: i32, is_grand: bool, collector: Guid, stage: Option<String>) -> Self {
+        Self {
+            id,
+            is_grand,
+            collector: Some(collector),
+            collected_at: Some(SystemTime::now()),
+            stage,
+        }
+    }
+}
+
+/// Stage a new save file starts in
+pub const NEW_SAVE_STAGE: &str = "CapWorldHomeStage";
+/// First stage after the intro where receiving moons is safe again
+pub const RESUME_SYNC_STAGE: &str = "WaterfallWorldHomeStage";
+/// Time given to the kingdom's arrival cutscene before moons are synced
+pub const RESUME_SYNC_DELAY: Duration = Duration::from_secs(15);
+
+/// Whether a player can currently be sent moons collected by others.
+///
+/// Receiving moons during the intro of a new save breaks the game, so syncing
+/// pauses when a player starts one and resumes a little after they reach
+/// Cascade Kingdom.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ShineSyncState {
+    #[default]
+    Syncing,
+    NewSave,
+    Resuming {
+        at: Instant,
+    },
+}
+
+impl ShineSyncState {
+    /// The state after the player entered `stage`
+    pub fn on_stage(self, stage: &str, scenario: u8, now: Instant) -> Self {
+        match self {
+            _ if stage == NEW_SAVE_STAGE && scenario == 0 => Self::NewSave,
+            Self::NewSave if stage == RESUME_SYNC_STAGE => Self::Resuming {
+                at: now + RESUME_SYNC_DELAY,
+            },
+            state => state,
+        }
+    }
+
+    pub fn can_sync(&self, now: Instant) -> bool {
+        match self {
+            Self::Syncing => true,
+            Self::NewSave => false,
+            Self::Resuming { at } => now >= *at,
+        }
+    }
+}
+
+/// Shine files written before collectors were tracked only hold the ids
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredShine {
+    Id(i32),
+    Record(ShineRecord),
+}
+
+pub fn load_shines(filename: &str) -> Result<ShineBag> {
+    let file = File::open(filename)?;
+    let reader = BufReader::new(file);
+    let shines: Vec<StoredShine> = serde_json::from_reader(reader)?;
+
+    Ok(shines
+        .into_iter()
+        .map(|shine| match shine {
+            StoredShine::Id(id) => ShineRecord {
+                id,
+                is_grand: false,
+                collector: None,
+                collected_at: None,
+                stage: None,
+            },
+            StoredShine::Record(record) => record,
+        })
+        .map(|record| (record.id, record))
+        .collect())
+}
+
+pub fn save_shines(filename: &str, shines: &ShineBag) -> Result<()> {
+    let mut records: Vec<_> = shines.values().collect();
+    records.sort_by_key(|record| record.id);
+    write_json_atomic(Path::new(filename), &records, false)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn new_save_pauses_sync() {
+        let now = Instant::now();
+        let state = ShineSyncState::default();
+        assert!(state.can_sync(now));
+
+        let state = state.on_stage(NEW_SAVE_STAGE, 1, now);
+        assert_eq!(state, ShineSyncState::Syncing);
+        let state = state.on_stage(NEW_SAVE_STAGE, 0, now);
+        assert!(!state.can_sync(now));
+        let state = state.on_stage("CapWorldTowerStage", 0, now);
+        assert!(!state.can_sync(now));
+
+        let state = state.on_stage(RESUME_SYNC_STAGE, 1, now);
+        assert!(!state.can_sync(now));
+        assert!(state.can_sync(now + RESUME_SYNC_DELAY));
+        assert_eq!(state.on_stage("SandWorldHomeStage", 1, now), state);
+    }
+
+    #[test]
+    fn loads_old_and_new_shine_files() {
+        let dir = std::env::temp_dir().join(format!("smoo-shines-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let filename = dir.join("moons.json");
+        let filename = filename.to_str().unwrap();
+
+        std::fs::write(filename, "[3, 5]").unwrap();
+        let shines = load_shines(filename).unwrap();
+        assert_eq!(shines.len(), 2);
+        assert_eq!(shines[&3].collector, None);
+
+        let mut shines = ShineBag::new();
+        let stage = Some("SandWorldHomeStage".to_string());
+        shines.insert(7, ShineRecord::new(7, true, [1; 16].into(), stage));
+        save_shines(filename, &shines).unwrap();
+        assert_eq!(load_shines(filename).unwrap(), shines);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
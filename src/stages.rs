@@ -0,0 +1,59 @@
+/// Kingdom aliases accepted by the console and the home stage they map to
+pub const KINGDOMS: &[(&str, &str)] = &[
+    ("cap", "CapWorldHomeStage"),
+    ("cascade", "WaterfallWorldHomeStage"),
+    ("sand", "SandWorldHomeStage"),
+    ("lake", "LakeWorldHomeStage"),
+    ("wooded", "ForestWorldHomeStage"),
+    ("cloud", "CloudWorldHomeStage"),
+    ("lost", "ClashWorldHomeStage"),
+    ("metro", "CityWorldHomeStage"),
+    ("snow", "SnowWorldHomeStage"),
+    ("sea", "SeaWorldHomeStage"),
+    ("lunch", "LavaWorldHomeStage"),
+    ("ruined", "BossRaidWorldHomeStage"),
+    ("bowser", "SkyWorldHomeStage"),
+    ("moon", "MoonWorldHomeStage"),
+    ("mush", "PeachWorldHomeStage"),
+    ("dark", "Special1WorldHomeStage"),
+    ("darker", "Special2WorldHomeStage"),
+    ("odyssey", "HomeShipInsideStage"),
+];
+
+/// Resolves console input to a stage name.
+///
+/// Accepts a kingdom alias or a known stage name. Any other stage (such as a
+/// sub area) can be forced through by suffixing it with `!`.
+pub fn input_to_stage(input: &str) -> Option<String> {
+    if let Some(stage) = input.strip_suffix('!') {
+        return (!stage.is_empty()).then(|| stage.to_string());
+    }
+
+    KINGDOMS
+        .iter()
+        .find(|(alias, stage)| alias.eq_ignore_ascii_case(input) || *stage == input)
+        .map(|(_, stage)| stage.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn resolves_stage_input() {
+        assert_eq!(
+            input_to_stage("Sand").as_deref(),
+            Some("SandWorldHomeStage")
+        );
+        assert_eq!(
+            input_to_stage("SandWorldHomeStage").as_deref(),
+            Some("SandWorldHomeStage")
+        );
+        assert_eq!(
+            input_to_stage("SandWorldPyramid000Stage!").as_deref(),
+            Some("SandWorldPyramid000Stage")
+        );
+        assert_eq!(input_to_stage("NotAStage"), None);
+        assert_eq!(input_to_stage("!"), None);
+    }
+}
@@ -0,0 +1,155 @@
+use std::{fmt::Display, net::IpAddr};
+
+use serde::Serialize;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ConnectionMode {
+    #[default]
+    Tcp,
+    Udp,
+    /// Udp was up but went silent, so movement is sent over tcp for now
+    TcpFallback,
+}
+
+impl Display for ConnectionMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tcp => write!(f, "TCP"),
+            Self::Udp => write!(f, "UDP"),
+            Self::TcpFallback => write!(f, "TCP (UDP lost)"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct TagStatus {
+    pub is_seeking: bool,
+    pub seconds: u64,
+}
+
+/// Snapshot of a connected player as shown by the `list` command
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PlayerStatus {
+    pub guid: String,
+    pub name: String,
+    pub ip: Option<IpAddr>,
+    pub mode: ConnectionMode,
+    pub stage: Option<String>,
+    pub scenario: Option<u8>,
+    pub body: String,
+    pub cap: String,
+    pub capture: Option<String>,
+    pub tag: Option<TagStatus>,
+    pub connected_seconds: u64,
+}
+
+const HEADERS: [&str; 9] = [
+    "Guid",
+    "Name",
+    "IP",
+    "Mode",
+    "Stage",
+    "Costume",
+    "Capture",
+    "Tag",
+    "Connected",
+];
+
+impl PlayerStatus {
+    fn columns(&self) -> [String; 9] {
+        let or_dash = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+        let stage = self.stage.as_ref().map(|stage| match self.scenario {
+            Some(scenario) => format!("{} ({})", stage, scenario),
+            None => stage.clone(),
+        });
+        let tag = self.tag.as_ref().map(|tag| {
+            let role = if tag.is_seeking { "seeking" } else { "hiding" };
+            format!("{} {}", role, format_duration(tag.seconds))
+        });
+
+        [
+            self.guid.clone(),
+            self.name.clone(),
+            or_dash(self.ip.map(|ip| ip.to_string())),
+            self.mode.to_string(),
+            or_dash(stage),
+            format!("{}/{}", self.body, self.cap),
+            or_dash(self.capture.clone()),
+            or_dash(tag),
+            format_duration(self.connected_seconds),
+        ]
+    }
+}
+
+/// Formats players as a table with one aligned column per field
+pub fn format_table(players: &[PlayerStatus]) -> String {
+    let rows: Vec<_> = players.iter().map(PlayerStatus::columns).collect();
+    let mut widths: Vec<usize> = HEADERS.iter().map(|h| h.len()).collect();
+    for row in &rows {
+        for (width, column) in widths.iter_mut().zip(row) {
+            *width = (*width).max(column.chars().count());
+        }
+    }
+
+    let format_row = |columns: &mut dyn Iterator<Item = &str>| {
+        let line = columns
+            .zip(&widths)
+            .map(|(column, width)| format!("{:width$}", column, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        line.trim_end().to_string()
+    };
+
+    let mut lines = vec![format_row(&mut HEADERS.iter().copied())];
+    for row in &rows {
+        lines.push(format_row(&mut row.iter().map(String::as_str)));
+    }
+    lines.join("\n")
+}
+
+pub fn format_duration(total_seconds: u64) -> String {
+    let (hours, minutes, seconds) = (
+        total_seconds / 3600,
+        total_seconds / 60 % 60,
+        total_seconds % 60,
+    );
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn aligned_table() {
+        let player = PlayerStatus {
+            guid: "00000000-0000-0000-0000-000000000001".to_string(),
+            name: "Mario".to_string(),
+            ip: Some("127.0.0.1".parse().unwrap()),
+            mode: ConnectionMode::Udp,
+            stage: Some("SandWorldHomeStage".to_string()),
+            scenario: Some(2),
+            body: "Mario".to_string(),
+            cap: "Mario".to_string(),
+            capture: None,
+            tag: Some(TagStatus {
+                is_seeking: false,
+                seconds: 75,
+            }),
+            connected_seconds: 3725,
+        };
+        let table = format_table(&[player]);
+        let lines: Vec<_> = table.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].find("Name"), lines[1].find("Mario"));
+        assert!(lines[1].contains("SandWorldHomeStage (2)"));
+        assert!(lines[1].contains("hiding 1:15"));
+        assert!(lines[1].ends_with("1:02:05"));
+    }
+}
@@ -0,0 +1,209 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+use crate::guid::Guid;
+
+/// How often the server pushes authoritative hiding times to players
+pub const TAG_UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TagPhase {
+    #[default]
+    Idle,
+    Countdown {
+        ends: Instant,
+    },
+    Running {
+        last_update: Instant,
+    },
+}
+
+/// Server side state of a hide and seek game.
+///
+/// Hiding time is only counted here, so a client reporting its own time
+/// can't change who wins.
+#[derive(Debug, Default)]
+pub struct TagGame {
+    phase: TagPhase,
+    seekers: HashSet<Guid>,
+    hiders: HashSet<Guid>,
+    times: HashMap<Guid, Duration>,
+}
+
+impl TagGame {
+    pub fn start(
+        &mut self,
+        players: impl IntoIterator<Item = Guid>,
+        seekers: &HashSet<Guid>,
+        countdown: Duration,
+        now: Instant,
+    ) {
+        self.seekers.clear();
+        self.hiders.clear();
+        self.times.clear();
+
+        for guid in players {
+            if seekers.contains(&guid) {
+                self.seekers.insert(guid);
+            } else {
+                self.hiders.insert(guid);
+                self.times.insert(guid, Duration::ZERO);
+            }
+        }
+
+        self.phase = TagPhase::Countdown {
+            ends: now + countdown,
+        };
+    }
+
+    /// Ends the game and forgets its roles and times
+    pub fn stop(&mut self) {
+        self.phase = TagPhase::Idle;
+        self.seekers.clear();
+        self.hiders.clear();
+        self.times.clear();
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.phase != TagPhase::Idle
+    }
+
+    pub fn is_running(&self) -> bool {
+        matches!(self.phase, TagPhase::Running { .. })
+    }
+
+    /// Whether the player is seeking, or `None` if they aren't part of the game
+    pub fn is_seeking(&self, guid: &Guid) -> Option<bool> {
+        if self.seekers.contains(guid) {
+            Some(true)
+        } else if self.hiders.contains(guid) {
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    /// Ends the countdown once it has elapsed and adds the time since the
+    /// last update to every hider still in hiding
+    pub fn update(&mut self, now: Instant) {
+        match &mut self.phase {
+            TagPhase::Countdown { ends } if now >= *ends => {
+                self.phase = TagPhase::Running { last_update: now };
+            }
+            TagPhase::Running { last_update } => {
+                let elapsed = now.saturating_duration_since(*last_update);
+                *last_update = now;
+                for hider in &self.hiders {
+                    *self.times.entry(*hider).or_default() += elapsed;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Turns a hider into a seeker, returning false if they weren't hiding
+    pub fn catch(&mut self, guid: &Guid) -> bool {
+        if !self.hiders.remove(guid) {
+            return false;
+        }
+        self.seekers.insert(*guid);
+        true
+    }
+
+    pub fn set_seeking(&mut self, guid: Guid, is_seeking: bool) {
+        if is_seeking {
+            self.hiders.remove(&guid);
+            self.seekers.insert(guid);
+        } else {
+            self.seekers.remove(&guid);
+            self.hiders.insert(guid);
+            self.times.entry(guid).or_default();
+        }
+    }
+
+    pub fn set_time(&mut self, guid: Guid, time: Duration) {
+        self.times.insert(guid, time);
+    }
+
+    pub fn remove_player(&mut self, guid: &Guid) {
+        self.seekers.remove(guid);
+        self.hiders.remove(guid);
+        self.times.remove(guid);
+    }
+
+    pub fn time(&self, guid: &Guid) -> Option<Duration> {
+        self.times.get(guid).copied()
+    }
+
+    pub fn hider_times(&self) -> impl Iterator<Item = (Guid, Duration)> + '_ {
+        self.hiders
+            .iter()
+            .map(|guid| (*guid, self.times.get(guid).copied().unwrap_or_default()))
+    }
+
+    /// A running game is over once nobody is left to hide or nobody is left to seek
+    pub fn is_finished(&self) -> bool {
+        self.is_running() && (self.hiders.is_empty() || self.seekers.is_empty())
+    }
+
+    /// The player who stayed hidden the longest
+    pub fn winner(&self) -> Option<(Guid, Duration)> {
+        self.times
+            .iter()
+            .max_by_key(|(_, time)| **time)
+            .map(|(guid, time)| (*guid, *time))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn guid(n: u8) -> Guid {
+        [n; 16].into()
+    }
+
+    #[test]
+    fn countdown_then_hiding_time() {
+        let now = Instant::now();
+        let mut game = TagGame::default();
+        let seekers = HashSet::from([guid(1)]);
+        game.start([guid(1), guid(2)], &seekers, Duration::from_secs(5), now);
+
+        game.update(now + Duration::from_secs(1));
+        assert!(!game.is_running());
+        assert_eq!(game.time(&guid(2)), Some(Duration::ZERO));
+
+        game.update(now + Duration::from_secs(5));
+        assert!(game.is_running());
+        game.update(now + Duration::from_secs(8));
+        assert_eq!(game.time(&guid(2)), Some(Duration::from_secs(3)));
+        assert_eq!(game.time(&guid(1)), None);
+    }
+
+    #[test]
+    fn last_caught_hider_wins() {
+        let now = Instant::now();
+        let mut game = TagGame::default();
+        let seekers = HashSet::from([guid(1)]);
+        game.start([guid(1), guid(2), guid(3)], &seekers, Duration::ZERO, now);
+        game.update(now);
+
+        game.update(now + Duration::from_secs(10));
+        assert!(game.catch(&guid(2)));
+        assert!(!game.catch(&guid(2)));
+        assert!(!game.is_finished());
+
+        game.update(now + Duration::from_secs(30));
+        assert!(game.catch(&guid(3)));
+        assert!(game.is_finished());
+        assert_eq!(game.is_seeking(&guid(3)), Some(true));
+        assert_eq!(game.winner(), Some((guid(3), Duration::from_secs(30))));
+
+        game.stop();
+        assert_eq!(game.is_seeking(&guid(3)), None);
+        assert_eq!(game.winner(), None);
+    }
+}
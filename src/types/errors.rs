@@ -11,6 +11,16 @@ pub type Result<T> = std::result::Result<T, SMOError>;
 pub enum SMOError {
     #[error("Invalid id")]
     InvalidID(Guid),
+    #[error("Player not found: {0}")]
+    PlayerNotFound(String),
+    #[error("Invalid stage name: {0}")]
+    InvalidStage(String),
+    #[error("Invalid scenario: {0}")]
+    InvalidScenario(i8),
+    #[error("Message of {0} bytes is too long")]
+    MessageTooLong(usize),
+    #[error("A tag game needs at least one seeker")]
+    NoSeekers,
 
     #[error("Invalid encoding: {0}")]
     Encoding(#[from] EncodingError),
@@ -26,10 +36,16 @@ pub enum SMOError {
     ThreadJoin(#[from] JoinError),
     #[error("Failed to initialize client: {0}")]
     ClientInit(#[from] ClientInitError),
-    #[error("Invalid error")]
+    #[error("Invalid json: {0}")]
     JsonError(#[from] serde_json::Error),
     #[error("Udp not initialized")]
     UdpNotInit,
+    #[cfg(feature = "discord")]
+    #[error("Discord http error: {0}")]
+    DiscordHttp(#[from] reqwest::Error),
+    #[cfg(feature = "discord")]
+    #[error("Discord gateway error: {0}")]
+    DiscordGateway(String),
 }
 
 #[derive(Error, Debug)]
@@ -46,6 +62,12 @@ pub enum EncodingError {
     ConnectionReset,
     #[error("Connection closed by peer")]
     ConnectionClose,
+    #[error("Unknown packet type: {0}")]
+    UnknownPacketType(u16),
+    #[error("Packet data of {0} bytes exceeds the maximum packet size")]
+    PacketTooLarge(u16),
+    #[error("Packet data of {size} bytes is too short for packet type {tag}")]
+    PayloadTooShort { tag: u16, size: u16 },
     #[error("Serde error")]
     CustomError,
 }
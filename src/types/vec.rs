@@ -10,6 +10,9 @@ where
     R: Buf,
 {
     fn decode(buf: &mut R) -> Result<Self, EncodingError> {
+        if buf.remaining() < 12 {
+            return Err(EncodingError::NotEnoughData);
+        }
         let mut vec = Self::default();
         vec.x = buf.get_f32_le();
         vec.y = buf.get_f32_le();
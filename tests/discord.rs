@@ -0,0 +1,198 @@
+#![cfg(feature = "discord")]
+
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use smoo::{
+    cmds::{CliCommand, Command},
+    coordinator::ServerEvent,
+    discord::DiscordBot,
+    settings::DiscordSettings,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::{broadcast, mpsc},
+};
+use tokio_tungstenite::{accept_async, tungstenite::Message};
+
+const TOKEN: &str = "test-token";
+const CHANNEL: &str = "1234";
+
+#[derive(Debug)]
+struct PostedMessage {
+    path: String,
+    authorization: String,
+    content: String,
+}
+
+/// Minimal http server that records every request body as a posted message
+async fn mock_api(listener: TcpListener, posted: mpsc::Sender<PostedMessage>) {
+    loop {
+        let (stream, _) = listener.accept().await.unwrap();
+        tokio::spawn(handle_api_conn(stream, posted.clone()));
+    }
+}
+
+async fn handle_api_conn(stream: TcpStream, posted: mpsc::Sender<PostedMessage>) {
+    let mut reader = BufReader::new(stream);
+    loop {
+        let mut request_line = String::new();
+        if reader.read_line(&mut request_line).await.unwrap_or(0) == 0 {
+            return;
+        }
+        let path = request_line
+            .split(' ')
+            .nth(1)
+            .unwrap_or_default()
+            .to_string();
+
+        let mut content_length = 0;
+        let mut authorization = String::new();
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).await.unwrap();
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            let (name, value) = header.split_once(": ").unwrap();
+            match name.to_ascii_lowercase().as_str() {
+                "content-length" => content_length = value.parse().unwrap(),
+                "authorization" => authorization = value.to_string(),
+                _ => {}
+            }
+        }
+
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        let content = body["content"].as_str().unwrap().to_string();
+        posted
+            .send(PostedMessage {
+                path,
+                authorization,
+                content,
+            })
+            .await
+            .unwrap();
+
+        let response =
+            "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: 2\r\n\r\n{}";
+        reader
+            .get_mut()
+            .write_all(response.as_bytes())
+            .await
+            .unwrap();
+    }
+}
+
+fn message_create(content: &str, author: &str, roles: &[&str], bot: bool) -> Message {
+    let payload = json!({
+        "op": 0,
+        "s": 1,
+        "t": "MESSAGE_CREATE",
+        "d": {
+            "channel_id": CHANNEL,
+            "content": content,
+            "author": { "id": author, "bot": bot },
+            "member": { "roles": roles },
+        },
+    });
+    Message::Text(payload.to_string())
+}
+
+async fn next_post(posted: &mut mpsc::Receiver<PostedMessage>) -> PostedMessage {
+    tokio::time::timeout(Duration::from_secs(5), posted.recv())
+        .await
+        .expect("Timed out waiting for message")
+        .unwrap()
+}
+
+#[tokio::test]
+async fn commands_and_events() {
+    let gateway = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let api = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let gateway_url = format!("ws://{}", gateway.local_addr().unwrap());
+    let api_url = format!("http://{}", api.local_addr().unwrap());
+
+    let (posted_tx, mut posted) = mpsc::channel(10);
+    tokio::spawn(mock_api(api, posted_tx));
+
+    // Stand in for the coordinator
+    let (to_coord, mut from_bot) = mpsc::channel(10);
+    tokio::spawn(async move {
+        while let Some(cmd) = from_bot.recv().await {
+            if let Command::Cli(CliCommand::List { .. }, reply) = cmd {
+                let _ = reply.send(Ok("Mario (00000000-0000-0000-0000-000000000000)".into()));
+            }
+        }
+    });
+
+    let settings = DiscordSettings {
+        token: Some(TOKEN.to_string()),
+        prefix: "$".to_string(),
+        log_channel: Some(CHANNEL.to_string()),
+        admin_users: ["1".to_string()].into(),
+        admin_roles: ["10".to_string()].into(),
+    };
+    let bot = DiscordBot::new(&settings, to_coord)
+        .unwrap()
+        .with_urls(gateway_url, api_url);
+    let (events, events_rx) = broadcast::channel(8);
+    tokio::spawn(bot.run(events_rx));
+
+    let (stream, _) = gateway.accept().await.unwrap();
+    let mut ws = accept_async(stream).await.unwrap();
+    let hello = json!({ "op": 10, "d": { "heartbeat_interval": 45000 } });
+    ws.send(Message::Text(hello.to_string())).await.unwrap();
+
+    let identify = match ws.next().await.unwrap().unwrap() {
+        Message::Text(text) => serde_json::from_str::<Value>(&text).unwrap(),
+        msg => panic!("Unexpected message {:?}", msg),
+    };
+    assert_eq!(identify["op"], 2);
+    assert_eq!(identify["d"]["token"], TOKEN);
+
+    // Other bots, unprefixed messages and non-admins are ignored
+    ws.send(message_create("$list", "1", &[], true))
+        .await
+        .unwrap();
+    ws.send(message_create("list", "1", &[], false))
+        .await
+        .unwrap();
+    ws.send(message_create("$list", "2", &["20"], false))
+        .await
+        .unwrap();
+    ws.send(message_create("$list", "1", &[], false))
+        .await
+        .unwrap();
+
+    let reply = next_post(&mut posted).await;
+    assert_eq!(reply.path, format!("/channels/{}/messages", CHANNEL));
+    assert_eq!(reply.authorization, format!("Bot {}", TOKEN));
+    assert!(reply.content.starts_with("```"));
+    assert!(reply.content.contains("Mario"));
+
+    // Admin roles work for any user
+    ws.send(message_create("$list", "3", &["10"], false))
+        .await
+        .unwrap();
+    let reply = next_post(&mut posted).await;
+    assert!(reply.content.contains("Mario"));
+
+    events
+        .send(ServerEvent::PlayerJoined {
+            name: "Luigi".to_string(),
+        })
+        .unwrap();
+    let logged = next_post(&mut posted).await;
+    assert_eq!(logged.content, "Luigi joined the server");
+}
+
+#[test]
+fn no_token_no_bot() {
+    let (to_coord, _) = mpsc::channel(1);
+    assert!(DiscordBot::new(&DiscordSettings::default(), to_coord).is_none());
+}
@@ -1,18 +1,25 @@
-use bytes::{Buf, BufMut, Bytes, BytesMut};
+use bytes::{BufMut, BytesMut};
 use quickcheck::quickcheck;
-use smoo::cmds::TagCommand;
+use serde::{Deserialize, Serialize};
 use smoo::guid::Guid;
 use smoo::net::encoding::{Decodable, Encodable};
-use smoo::net::{Packet, TagUpdate};
+use smoo::net::protocol::{Extensions, ProtocolInfo};
+use smoo::net::{
+    fixed_str, from_bytes, to_bytes, ConnectionType, Packet, PacketData, PacketType, RawPacket,
+    MAX_PACKET_SIZE,
+};
+use smoo::types::{EncodingError, Quaternion, Vector3};
 
-// quickcheck! {
-//     fn round_trip(p: Packet) -> bool {
-//         let mut buff = BytesMut::with_capacity(1000);
-//         p.encode(&mut buff).map(|_| Packet::decode(&mut buff).map(|de_p| de_p == p).unwrap_or(false)).unwrap_or(false)
-//     }
-// }
+quickcheck! {
+    fn round_trip(p: Packet) -> bool {
+        let mut buff = BytesMut::with_capacity(MAX_PACKET_SIZE);
+        p.encode(&mut buff).unwrap();
+        Packet::decode(&mut buff).unwrap() == p && buff.is_empty()
+    }
+}
 
 #[test]
+#[allow(clippy::octal_escapes)]
 fn bad_tag_packet() {
     let bad_data = b"~\x80W4\xba-\0\x10\xaf\xed_\xea\xc5h\x15K\x03\0P\00v\xa5E\0\0\xf0B\xa1R\x9fE\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\x01FlyingWaitR\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\xccL>";
 
@@ -29,3 +36,219 @@ fn bad_tag_packet() {
     let decode = Packet::decode(&mut buff).unwrap();
     assert_eq!(bad_packet, decode)
 }
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct GamePayload {
+    is_2d: bool,
+    scenario_num: u8,
+    #[serde(
+        serialize_with = "fixed_str::serialize::<_, 0x40>",
+        deserialize_with = "fixed_str::deserialize::<_, 0x40>"
+    )]
+    stage: String,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct PlayerPayload {
+    pos: (f32, f32, f32),
+    rot: [f32; 4],
+    animation_blend_weights: [f32; 6],
+    act: u16,
+    sub_act: u16,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+enum Update {
+    Time { seconds: u8, minutes: u16 },
+    State(bool),
+}
+
+fn encoded_data(data: PacketData) -> BytesMut {
+    let mut buff = BytesMut::with_capacity(300);
+    Packet::new(Guid::default(), data)
+        .encode(&mut buff)
+        .unwrap();
+    buff.split_off(20)
+}
+
+#[test]
+fn serde_matches_packet_encoding() {
+    let game = GamePayload {
+        is_2d: true,
+        scenario_num: 3,
+        stage: "SandWorldHomeStage".to_string(),
+    };
+    let expected = encoded_data(PacketData::Game {
+        is_2d: game.is_2d,
+        scenario_num: game.scenario_num,
+        stage: game.stage.clone(),
+    });
+    let bytes = to_bytes(&game).unwrap();
+    assert_eq!(bytes, expected);
+    assert_eq!(from_bytes::<GamePayload>(&bytes).unwrap(), game);
+
+    let player = PlayerPayload {
+        pos: (1.0, -2.5, 300.0),
+        rot: [0.0, 0.5, 0.0, 1.0],
+        animation_blend_weights: [0.1, 0.2, 0.3, 0.4, 0.5, 0.6],
+        act: 12,
+        sub_act: 258,
+    };
+    let expected = encoded_data(PacketData::Player {
+        pos: Vector3::new(player.pos.0, player.pos.1, player.pos.2),
+        rot: Quaternion::new(player.rot[3], player.rot[0], player.rot[1], player.rot[2]),
+        animation_blend_weights: player.animation_blend_weights,
+        act: player.act,
+        sub_act: player.sub_act,
+    });
+    let bytes = to_bytes(&player).unwrap();
+    assert_eq!(bytes, expected);
+    assert_eq!(from_bytes::<PlayerPayload>(&bytes).unwrap(), player);
+}
+
+#[test]
+fn serde_enums_and_errors() {
+    let update = Update::Time {
+        seconds: 5,
+        minutes: 513,
+    };
+    let bytes = to_bytes(&update).unwrap();
+    assert_eq!(&bytes[..], &[0, 0, 5, 1, 2]);
+    assert_eq!(from_bytes::<Update>(&bytes).unwrap(), update);
+    assert_eq!(
+        from_bytes::<Update>(&[1, 0, 1]).unwrap(),
+        Update::State(true)
+    );
+
+    assert!(matches!(
+        from_bytes::<GamePayload>(&bytes),
+        Err(EncodingError::NotEnoughData)
+    ));
+    let too_long = GamePayload {
+        is_2d: false,
+        scenario_num: 0,
+        stage: "x".repeat(0x41),
+    };
+    assert!(to_bytes(&too_long).is_err());
+}
+
+#[test]
+fn raw_frames_split_from_stream() {
+    let player = Packet::new(
+        [7; 16].into(),
+        PacketData::Player {
+            pos: Vector3::new(1.0, 2.0, 3.0),
+            rot: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            animation_blend_weights: [0.0; 6],
+            act: 4,
+            sub_act: 5,
+        },
+    );
+    let shine = Packet::new(
+        [7; 16].into(),
+        PacketData::Shine {
+            shine_id: 42,
+            is_grand: true,
+        },
+    );
+
+    let mut stream = BytesMut::with_capacity(300);
+    player.encode(&mut stream).unwrap();
+    shine.encode(&mut stream).unwrap();
+    let mut partial = BytesMut::with_capacity(100);
+    player.encode(&mut partial).unwrap();
+    stream.put(&partial[..30]);
+
+    let frame = RawPacket::split_from(&mut stream).unwrap().unwrap();
+    assert_eq!(frame.header.packet_type(), Some(PacketType::Player));
+    assert!(!frame.needs_decode());
+    assert_eq!(frame, RawPacket::from_packet(&player).unwrap());
+    assert_eq!(frame.decode().unwrap(), player);
+
+    let frame = RawPacket::split_from(&mut stream).unwrap().unwrap();
+    assert!(frame.needs_decode());
+    assert_eq!(frame.decode().unwrap(), shine);
+
+    assert_eq!(RawPacket::split_from(&mut stream).unwrap(), None);
+    assert_eq!(stream.len(), 30);
+}
+
+#[test]
+fn malformed_packets_are_errors() {
+    let header = |tag: u16, size: u16| {
+        let mut buff = BytesMut::with_capacity(100);
+        buff.put_slice(&[9; 16]);
+        buff.put_u16_le(tag);
+        buff.put_u16_le(size);
+        buff
+    };
+
+    let mut short_player = header(2, 4);
+    short_player.put_slice(&[0; 4]);
+    assert!(matches!(
+        Packet::decode(&mut short_player.clone()),
+        Err(EncodingError::PayloadTooShort { tag: 2, size: 4 })
+    ));
+    let frame = RawPacket::split_from(&mut short_player).unwrap().unwrap();
+    assert!(frame.check_size().is_err());
+
+    let mut oversized = header(3, 0x1000);
+    assert!(matches!(
+        RawPacket::split_from(&mut oversized),
+        Err(EncodingError::PacketTooLarge(0x1000))
+    ));
+
+    let mut padded_shine = header(9, 8);
+    padded_shine.put_i32_le(7);
+    padded_shine.put_slice(&[1, 0, 0, 0]);
+    padded_shine.put_slice(&[0xff; 3]);
+    let packet = Packet::decode(&mut padded_shine).unwrap();
+    assert_eq!(
+        packet.data,
+        PacketData::Shine {
+            shine_id: 7,
+            is_grand: true
+        }
+    );
+    assert_eq!(&padded_shine[..], &[0xff; 3]);
+}
+
+#[test]
+fn protocol_info_piggy_backs_on_connect() {
+    let stock = Packet::new(
+        [4; 16].into(),
+        PacketData::Connect {
+            c_type: ConnectionType::FirstConnection,
+            max_player: 8,
+            client_name: "Luigi".to_string(),
+            protocol: None,
+        },
+    );
+    let protocol = ProtocolInfo {
+        version: 1,
+        extensions: Extensions::PING,
+    };
+    let extended = Packet::new(
+        [4; 16].into(),
+        PacketData::Connect {
+            c_type: ConnectionType::FirstConnection,
+            max_player: 8,
+            client_name: "Luigi".to_string(),
+            protocol: Some(protocol),
+        },
+    );
+    assert_eq!(stock.data_size, 38);
+    assert_eq!(extended.data_size, 38 + 10);
+
+    let mut buff = BytesMut::with_capacity(100);
+    extended.encode(&mut buff).unwrap();
+    let decoded = Packet::decode(&mut buff).unwrap();
+    assert_eq!(decoded.data.protocol(), Some(&protocol));
+
+    // Padding from a stock client is not protocol info
+    let mut padded = stock.clone();
+    padded.data_size += 10;
+    padded.encode(&mut buff).unwrap();
+    let decoded = Packet::decode(&mut buff).unwrap();
+    assert_eq!(decoded.data.protocol(), None);
+}